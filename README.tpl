@@ -18,6 +18,9 @@
 
 [Command line summary](#command-summary)
 
+Commands print their results to stdout, logs, warnings and progress go to stderr so the
+output can be piped to other tools.
+
 # Feature unification, what does this mean for me as a user?
 
 As a part of working with workspaces cargo performs feature unification:
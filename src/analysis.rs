@@ -0,0 +1,478 @@
+//! Library entry point for feature graph analysis
+//!
+//! [`FeatGraph`] and friends are public but considered semi-internal: their shape follows
+//! whatever cargo-hackerman commands need at the moment. [`analyze`] and [`Analysis`] are
+//! meant for downstream tools that want to inspect a workspace without shelling out to
+//! `cargo hackerman`.
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! let meta = cargo_metadata::MetadataCommand::new().exec()?;
+//! let platform = target_spec::Platform::current()?;
+//! let target = cargo_hackerman::Target::from(platform.triple_str());
+//! let analysis = cargo_hackerman::analyze(&meta, vec![target])?;
+//!
+//! for (package, features) in analysis.features() {
+//!     println!("{} {}: {features:?}", package.name, package.version);
+//! }
+//! for (name, copies) in analysis.duplicates() {
+//!     println!("{name} is present in {} versions", copies.len());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`compute_unification`] previews changes `cargo hackerman hack` would make without touching
+//! any files.
+
+use crate::{
+    feat_graph::{Feat, FeatGraph, Feature},
+    hack::{
+        collect_features_from, get_changeset, Collect, DetachedDepTree, ExtraEdges, MemberConfig,
+    },
+    metadata::{Resolver, Target},
+    source::{ChangePackage, Registries},
+};
+use cargo_metadata::{Metadata, Package};
+use petgraph::{
+    algo::{condensation, toposort},
+    graph::{Graph, NodeIndex},
+    visit::{Dfs, EdgeRef},
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Resolved feature graph of a workspace, restricted to the given target
+pub struct Analysis<'a> {
+    graph: FeatGraph<'a>,
+    /// base node of every used package mapped to all of its enabled features
+    enabled: DetachedDepTree,
+}
+
+/// Build a feature graph for `meta` and calculate features enabled when building the whole
+/// workspace for `targets`
+///
+/// Cfg flags of each target are as reported by `rustc --print=cfg`, they are used to evaluate
+/// `cfg(...)` style target dependencies. A dependency is used if any of the targets uses it.
+pub fn analyze(meta: &Metadata, targets: Vec<Target>) -> anyhow::Result<Analysis<'_>> {
+    let mut graph = FeatGraph::init(meta, targets)?;
+    graph.shrink_to_target()?;
+
+    let mut enabled = BTreeMap::new();
+    collect_features_from(
+        &mut Dfs::new(&graph.features, graph.root),
+        &graph,
+        &mut enabled,
+        Collect::DevTarget,
+        &ExtraEdges::new(),
+    );
+
+    Ok(Analysis { graph, enabled })
+}
+
+/// Dependency changes needed to unify features of workspace members for `targets`
+///
+/// Same changes as `cargo hackerman hack` makes with the default options, except for settings
+/// from `[workspace.metadata.hackerman]` - those are up to the caller. Members are ordered
+/// the same way as in `meta`, members with nothing to change are omitted.
+///
+/// `resolver` is usually [`Resolver::of_workspace`].
+pub fn compute_unification<'a>(
+    meta: &'a Metadata,
+    resolver: Resolver,
+    targets: Vec<Target>,
+    no_dev: bool,
+) -> anyhow::Result<Vec<(&'a Package, Vec<ChangePackage<'a>>)>> {
+    let registries = Registries::load(&meta.workspace_root);
+    let mut graph = FeatGraph::init(meta, targets)?;
+    let (changeset, _) = get_changeset(
+        &mut graph,
+        resolver,
+        &MemberConfig {
+            no_dev,
+            ..MemberConfig::default()
+        },
+    )?;
+    changeset
+        .into_iter()
+        .map(|(member, changes)| {
+            let changes = changes
+                .into_iter()
+                .map(|change| ChangePackage::make(member, change, &registries))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok((member.package(), changes))
+        })
+        .collect()
+}
+
+/// Numeric overview of a dependency graph, see [`Analysis::stats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Used packages, including workspace members
+    pub crates: usize,
+    /// Enabled named features of all the used packages
+    pub features: usize,
+    /// Crates used in more than one version
+    pub duplicates: usize,
+    /// Dependencies in the longest chain of normal and build dependencies
+    pub depth: usize,
+    /// Workspace members
+    pub members: usize,
+}
+
+impl<'a> Analysis<'a> {
+    /// Underlying feature graph
+    #[must_use]
+    pub const fn graph(&self) -> &FeatGraph<'a> {
+        &self.graph
+    }
+
+    /// Underlying feature graph, for operations not covered by `Analysis`
+    #[must_use]
+    pub fn into_graph(self) -> FeatGraph<'a> {
+        self.graph
+    }
+
+    /// All the packages used by the workspace, including workspace members
+    pub fn packages(&self) -> impl Iterator<Item = &'a Package> + '_ {
+        self.enabled
+            .keys()
+            .filter_map(|&ix| Some(self.graph.features[ix].pid()?.package()))
+    }
+
+    /// All the used packages along with their unified named features
+    pub fn features(&self) -> impl Iterator<Item = (&'a Package, BTreeSet<&'a str>)> + '_ {
+        self.enabled.iter().filter_map(|(&base, feats)| {
+            let package = self.graph.features[base].pid()?.package();
+            let feats = feats
+                .iter()
+                .filter_map(|&ix| match self.graph.features[ix].fid()?.dep {
+                    Feat::Base => None,
+                    Feat::Named(name) => Some(name),
+                })
+                .collect::<BTreeSet<_>>();
+            Some((package, feats))
+        })
+    }
+
+    /// Unified named features of all the used copies of a crate
+    #[must_use]
+    pub fn features_of(&self, krate: &str) -> Vec<(&'a Package, BTreeSet<&'a str>)> {
+        self.features()
+            .filter(|(package, _)| package.name == krate)
+            .collect()
+    }
+
+    /// Crates that are used in more than one version, ordered by name
+    #[must_use]
+    pub fn duplicates(&self) -> BTreeMap<&'a str, Vec<&'a Package>> {
+        duplicates(self.packages())
+    }
+
+    /// Crates that are used in more than one version by normal dependencies, ignoring
+    /// everything that is only reachable through dev or build dependencies
+    #[must_use]
+    pub fn normal_duplicates(&self) -> BTreeMap<&'a str, Vec<&'a Package>> {
+        let mut enabled = BTreeMap::new();
+        collect_features_from(
+            &mut Dfs::new(&self.graph.features, self.graph.root),
+            &self.graph,
+            &mut enabled,
+            Collect::NormalOnly,
+            &ExtraEdges::new(),
+        );
+        duplicates(
+            enabled
+                .keys()
+                .filter_map(|&ix| Some(self.graph.features[ix].pid()?.package())),
+        )
+    }
+
+    /// Crate and feature counts along with the deepest dependency chain
+    ///
+    /// Dev dependencies are not followed when looking for the deepest chain, crates that
+    /// depend on each other are counted as a single step.
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        let features = self.features().map(|(_, feats)| feats.len()).sum();
+        let members = self
+            .enabled
+            .keys()
+            .filter(|&&ix| matches!(self.graph.features[ix], Feature::Workspace(_)))
+            .count();
+
+        // package level graph: base node of every package and links between them
+        let mut owner = BTreeMap::new();
+        let mut packages = Graph::<NodeIndex, ()>::new();
+        for (&base, feats) in &self.enabled {
+            let node = packages.add_node(base);
+            owner.insert(base, node);
+            for &feat in feats {
+                owner.insert(feat, node);
+            }
+        }
+        for edge in self.graph.features.edge_references() {
+            let (Some(&from), Some(&to)) = (owner.get(&edge.source()), owner.get(&edge.target()))
+            else {
+                continue;
+            };
+            // links inside of a package carry no dependency kinds
+            let link = edge.weight();
+            if from != to && (link.kinds.is_empty() || !link.is_dev_only()) {
+                packages.update_edge(from, to, ());
+            }
+        }
+
+        let dag = condensation(packages, true);
+        let mut depth = vec![0; dag.node_count()];
+        for node in toposort(&dag, None).expect("condensed graph is acyclic") {
+            for next in dag.neighbors(node) {
+                depth[next.index()] = depth[next.index()].max(depth[node.index()] + 1);
+            }
+        }
+
+        Stats {
+            crates: self.enabled.len(),
+            features,
+            duplicates: self.duplicates().len(),
+            depth: depth.into_iter().max().unwrap_or(0),
+            members,
+        }
+    }
+}
+
+pub(crate) fn duplicates<'a>(
+    packages: impl Iterator<Item = &'a Package>,
+) -> BTreeMap<&'a str, Vec<&'a Package>> {
+    let mut res = BTreeMap::new();
+    for package in packages {
+        res.entry(package.name.as_str())
+            .or_insert_with(Vec::new)
+            .push(package);
+    }
+    res.retain(|_, copies| copies.len() > 1);
+    res
+}
+
+/// Split copies of a crate into groups of semver compatible versions
+///
+/// Versions are compatible if they share the leftmost non-zero component, same as cargo treats
+/// `^` requirements: `1.2.0` and `1.3.0` are compatible, `0.1.0` and `0.2.0` are not. Groups
+/// are ordered by version, a group with a single package means a hard split.
+#[must_use]
+pub fn semver_groups<'a>(copies: &[&'a Package]) -> Vec<Vec<&'a Package>> {
+    let mut groups = BTreeMap::new();
+    for &package in copies {
+        let v = &package.version;
+        let key = match (v.major, v.minor) {
+            (0, 0) => (0, 0, v.patch),
+            (0, minor) => (0, minor, 0),
+            (major, _) => (major, 0, 0),
+        };
+        groups.entry(key).or_insert_with(Vec::new).push(package);
+    }
+    groups.into_values().collect()
+}
+
+/// Native library linked by more than one copy of a crate
+///
+/// Cargo allows only one package in the build to set a given `links` key, such duplicates
+/// fail to build no matter how features are unified. Resolver refuses to produce such a graph
+/// so this never triggers with metadata coming from `cargo metadata` directly, only with
+/// metadata that was edited or produced by some other tool and passed via `--metadata-from`.
+#[must_use]
+pub fn links_conflict<'a>(copies: &[&'a Package]) -> Option<&'a str> {
+    let mut seen = BTreeSet::new();
+    copies
+        .iter()
+        .filter_map(|package| package.links.as_deref())
+        .find(|&links| !seen.insert(links))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{analyze, compute_unification, links_conflict, semver_groups, Stats};
+    use crate::{
+        config::expand_target_aliases,
+        feat_graph::test::{get_demo_meta, get_demo_resolver},
+        metadata::Target,
+    };
+    use cargo_metadata::Package;
+    use cargo_platform::Cfg;
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        str::FromStr,
+    };
+
+    #[test]
+    fn target_features_are_unified() -> anyhow::Result<()> {
+        let meta = get_demo_meta(4)?;
+        let target = Target {
+            triple: "x86_64-unknown-linux-gnu".to_string(),
+            cfgs: vec![Cfg::from_str("target_family=\"unix\"")?],
+        };
+        let analysis = analyze(&meta, vec![target])?;
+
+        let beta = analysis.features_of("beta");
+        assert_eq!(beta.len(), 1);
+        assert!(beta[0].1.contains("unix"));
+        assert!(!beta[0].1.contains("windows"));
+        assert_eq!(analysis.packages().count(), 2);
+        assert!(analysis.duplicates().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn every_target_is_checked_with_its_own_cfgs() -> anyhow::Result<()> {
+        let meta = get_demo_meta(20)?;
+        let target = |triple: &str, family: &str| -> anyhow::Result<Target> {
+            let cfgs = [family.to_string(), format!("target_family=\"{family}\"")];
+            Ok(Target {
+                triple: triple.to_string(),
+                cfgs: cfgs
+                    .iter()
+                    .map(|c| Cfg::from_str(c))
+                    .collect::<Result<_, _>>()?,
+            })
+        };
+        let beta = |targets| -> anyhow::Result<BTreeSet<&str>> {
+            let analysis = analyze(&meta, targets)?;
+            let features = analysis.features_of("beta");
+            Ok(features.into_iter().flat_map(|(_, feats)| feats).collect())
+        };
+
+        let linux = target("x86_64-unknown-linux-gnu", "unix")?;
+        let macos = target("aarch64-apple-darwin", "unix")?;
+        let windows = target("x86_64-pc-windows-msvc", "windows")?;
+        assert_eq!(
+            beta(vec![linux.clone(), macos])?,
+            BTreeSet::from(["linux", "macos"])
+        );
+        // cfg(not(unix)) is evaluated for windows alone, not for a mix of both targets
+        assert_eq!(
+            beta(vec![linux, windows])?,
+            BTreeSet::from(["linux", "not-unix"])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unix_alias_checks_macos_dependencies() -> anyhow::Result<()> {
+        let meta = get_demo_meta(20)?;
+        let targets = expand_target_aliases(&["unix".to_string()])
+            .into_iter()
+            .map(|triple| Target {
+                triple,
+                cfgs: vec![Cfg::from_str("unix").unwrap()],
+            })
+            .collect();
+        let analysis = analyze(&meta, targets)?;
+        let (_, beta) = &analysis.features_of("beta")[0];
+        assert_eq!(*beta, BTreeSet::from(["linux", "macos"]));
+        Ok(())
+    }
+
+    #[test]
+    fn unification_is_computed_without_changes() -> anyhow::Result<()> {
+        let meta = get_demo_meta(14)?;
+        let changes = compute_unification(
+            &meta,
+            get_demo_resolver(14)?,
+            vec![Target::from("x86_64-unknown-linux-gnu")],
+            false,
+        )?;
+        assert_eq!(changes.len(), 1);
+        let (member, changes) = &changes[0];
+        assert_eq!(member.name, "alpha");
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].name, "gamma");
+        assert_eq!(changes[0].feats, BTreeSet::from(["one".to_string()]));
+        Ok(())
+    }
+
+    #[test]
+    fn semver_compatible_copies_are_grouped() -> anyhow::Result<()> {
+        let mut meta = get_demo_meta(15)?;
+        let analysis = analyze(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let omega = &analysis.duplicates()["omega"];
+        assert_eq!(semver_groups(omega).len(), 2);
+
+        for package in &mut meta.packages {
+            if package.name == "omega" && package.version.minor == 2 {
+                package.version = "0.1.5".parse()?;
+            }
+        }
+        let analysis = analyze(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let omega = &analysis.duplicates()["omega"];
+        let groups = semver_groups(omega);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn shared_links_key_is_a_conflict() -> anyhow::Result<()> {
+        let mut meta = get_demo_meta(15)?;
+        for package in &mut meta.packages {
+            if package.name == "omega" && package.version.minor == 2 {
+                package.links = Some("omega".to_string());
+            }
+        }
+        let analysis = analyze(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        assert_eq!(links_conflict(&analysis.duplicates()["omega"]), None);
+
+        for package in &mut meta.packages {
+            if package.name == "omega" {
+                package.links = Some("omega".to_string());
+            }
+        }
+        let analysis = analyze(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        assert_eq!(
+            links_conflict(&analysis.duplicates()["omega"]),
+            Some("omega")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn build_only_duplicates_are_not_normal() -> anyhow::Result<()> {
+        // alpha uses omega 0.1, build script of beta uses omega 0.2
+        let meta = get_demo_meta(24)?;
+        let analysis = analyze(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let versions = |dupes: BTreeMap<&str, Vec<&Package>>| {
+            dupes
+                .into_iter()
+                .map(|(name, packages)| {
+                    let versions = packages.iter().map(|p| p.version.to_string()).collect();
+                    (name.to_string(), versions)
+                })
+                .collect::<Vec<(String, Vec<String>)>>()
+        };
+        let omega = ["0.1.0", "0.2.0"].map(String::from).to_vec();
+        assert_eq!(
+            versions(analysis.duplicates()),
+            [("omega".to_string(), omega)]
+        );
+        assert!(analysis.normal_duplicates().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn stats_follow_the_deepest_chain() -> anyhow::Result<()> {
+        let meta = get_demo_meta(13)?;
+        let analysis = analyze(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        // alpha -> epsilon -> zeta, epsilon enables default and zeta both "one" and "two"
+        let expected = Stats {
+            crates: 4,
+            features: 3,
+            duplicates: 0,
+            depth: 2,
+            members: 2,
+        };
+        assert_eq!(analysis.stats(), expected);
+
+        let meta = get_demo_meta(15)?;
+        let stats = analyze(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?.stats();
+        assert_eq!((stats.duplicates, stats.members), (1, 3));
+        Ok(())
+    }
+}
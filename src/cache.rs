@@ -0,0 +1,197 @@
+//! On disk cache for `cargo metadata` results
+//!
+//! Cached metadata is valid as long as manifests of all the local packages, manifests matched
+//! by workspace member globs, cargo config files and the lock file stay the same - this is
+//! checked by comparing their modification times. Cache lives in the `hackerman` subdirectory of
+//! the cargo target directory, one file per manifest path.
+//!
+//! Before running `cargo metadata` the target directory is not known yet: it is looked up in
+//! `CARGO_TARGET_DIR`/`CARGO_BUILD_TARGET_DIR` or as `target` next to the manifest or one of
+//! the workspace roots above it. Cache for a target directory set with `build.target-dir` in
+//! cargo config is never found and `cargo metadata` runs every time.
+
+use cargo_metadata::{
+    camino::{Utf8Path, Utf8PathBuf},
+    Metadata,
+};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, info};
+
+fn cache_name(manifest_path: &Path) -> String {
+    let mut hasher = DefaultHasher::new();
+    manifest_path
+        .canonicalize()
+        .unwrap_or_else(|_| manifest_path.to_path_buf())
+        .hash(&mut hasher);
+    format!("metadata-{:x}.json", hasher.finish())
+}
+
+fn cache_file(target_dir: &Path, manifest_path: &Path) -> PathBuf {
+    target_dir.join("hackerman").join(cache_name(manifest_path))
+}
+
+/// Target directories `cargo metadata` might report for `manifest_path`
+fn target_dir_candidates(manifest_path: &Path) -> Vec<PathBuf> {
+    for var in ["CARGO_TARGET_DIR", "CARGO_BUILD_TARGET_DIR"] {
+        if let Some(dir) = std::env::var_os(var) {
+            let dir = PathBuf::from(dir);
+            return vec![dir.canonicalize().unwrap_or(dir)];
+        }
+    }
+    let manifest_path = manifest_path
+        .canonicalize()
+        .unwrap_or_else(|_| manifest_path.to_path_buf());
+    manifest_path
+        .ancestors()
+        .skip(1)
+        .filter(|dir| dir.join("Cargo.toml").is_file())
+        .map(|dir| dir.join("target"))
+        .collect()
+}
+
+/// Cargo config files that can change `[patch]`, registries or targets
+fn config_files(meta: &Metadata) -> Vec<Utf8PathBuf> {
+    let mut dirs = meta
+        .workspace_root
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .collect::<Vec<_>>();
+    if let Some(cwd) = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| Utf8PathBuf::try_from(cwd).ok())
+    {
+        dirs.extend(cwd.ancestors().map(|dir| dir.join(".cargo")));
+    }
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".cargo")));
+    if let Some(home) = cargo_home.and_then(|home| Utf8PathBuf::try_from(home).ok()) {
+        dirs.push(home);
+    }
+    dirs.into_iter()
+        .flat_map(|dir| [dir.join("config"), dir.join("config.toml")])
+        .collect()
+}
+
+/// Manifests matched by `[workspace] members`, a new glob member changes this list
+fn member_manifests(root: &Utf8Path) -> Vec<Utf8PathBuf> {
+    let Ok(patterns) = crate::toml::member_patterns(&root.join("Cargo.toml")) else {
+        return Vec::new();
+    };
+    patterns
+        .iter()
+        .filter_map(|pattern| glob::glob(root.join(pattern).join("Cargo.toml").as_str()).ok())
+        .flatten()
+        .filter_map(|path| Utf8PathBuf::try_from(path.ok()?).ok())
+        .collect()
+}
+
+/// Files that affect the dependency resolution
+fn inputs(meta: &Metadata) -> Vec<Utf8PathBuf> {
+    let mut res = meta
+        .packages
+        .iter()
+        .filter(|p| p.source.is_none())
+        .map(|p| p.manifest_path.clone())
+        .collect::<Vec<_>>();
+    res.push(meta.workspace_root.join("Cargo.toml"));
+    res.push(meta.workspace_root.join("Cargo.lock"));
+    res.extend(member_manifests(&meta.workspace_root));
+    res.extend(config_files(meta));
+    res.sort();
+    res.dedup();
+    res
+}
+
+fn fingerprint(meta: &Metadata, options: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    options.hash(&mut hasher);
+    for path in inputs(meta) {
+        path.hash(&mut hasher);
+        std::fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn load_from(path: &Path, target_dir: &Path, options: &[String]) -> Option<Metadata> {
+    let data = std::fs::read_to_string(path).ok()?;
+    let mut cached = serde_json::from_str::<serde_json::Value>(&data).ok()?;
+    let key = cached.get("key")?.as_u64()?;
+    let meta = serde_json::from_value::<Metadata>(cached.get_mut("metadata")?.take()).ok()?;
+    if meta.target_directory.as_std_path() != target_dir {
+        debug!(
+            "Cached metadata in {} is for a different target",
+            path.display()
+        );
+        None
+    } else if fingerprint(&meta, options) == key {
+        info!("Using cached metadata from {}", path.display());
+        Some(meta)
+    } else {
+        debug!("Cached metadata in {} is out of date", path.display());
+        None
+    }
+}
+
+/// Load cached metadata for `manifest_path`, `None` if it is missing or out of date
+#[must_use]
+pub fn load(manifest_path: &Path, options: &[String]) -> Option<Metadata> {
+    target_dir_candidates(manifest_path)
+        .into_iter()
+        .find_map(|dir| load_from(&cache_file(&dir, manifest_path), &dir, options))
+}
+
+/// Save metadata for `manifest_path` so it can be reused by the next run
+///
+/// Metadata is written to a temporary file first so a concurrent run never reads a partial one
+pub fn store(manifest_path: &Path, options: &[String], meta: &Metadata) -> anyhow::Result<()> {
+    let path = cache_file(meta.target_directory.as_std_path(), manifest_path);
+    let dir = path.parent().expect("cache file is in a directory");
+    std::fs::create_dir_all(dir)?;
+    let cached = serde_json::json!({
+        "key": fingerprint(meta, options),
+        "metadata": meta,
+    });
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(serde_json::to_string(&cached)?.as_bytes())?;
+    file.persist(&path)?;
+    debug!("Saved metadata to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, store};
+    use crate::feat_graph::test::copy_demo_workspace;
+
+    #[test]
+    fn cache_is_invalidated_by_cargo_config() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["2"])?;
+        let manifest = dir.path().join("2/Cargo.toml");
+        let meta = cargo_metadata::MetadataCommand::new()
+            .manifest_path(&manifest)
+            .other_options(vec!["--offline".to_string()])
+            .exec()?;
+        let options = vec!["--offline".to_string()];
+        store(&manifest, &options, &meta)?;
+
+        // cache lives in the target directory of the workspace, not in a shared location
+        assert!(meta.target_directory.join("hackerman").is_dir());
+        let cached = load(&manifest, &options).expect("metadata is cached");
+        assert_eq!(cached.packages, meta.packages);
+        assert!(load(&manifest, &[]).is_none());
+
+        std::fs::create_dir(dir.path().join("2/.cargo"))?;
+        std::fs::write(dir.path().join("2/.cargo/config.toml"), "")?;
+        assert!(load(&manifest, &options).is_none());
+        Ok(())
+    }
+}
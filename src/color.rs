@@ -0,0 +1,51 @@
+//! Highlighting for human readable output
+//!
+//! Colors are decided once in `main` from `--color` and `NO_COLOR`, everything else asks
+//! [`name`] and [`version`] to decorate the text.
+
+use std::{
+    fmt::Display,
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::opts::ColorChoice;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Decide if output should be colored, `auto` uses colors for terminals unless `NO_COLOR` is
+/// set to a non empty value
+pub fn init(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                && std::io::stdout().is_terminal()
+        }
+    };
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn paint(code: &str, text: impl Display) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        format!("\x1b[{code}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+/// Crate name, bold
+pub fn name(text: impl Display) -> String {
+    paint("1", text)
+}
+
+/// Crate version, green
+pub fn version(text: impl Display) -> String {
+    paint("32", text)
+}
+
+/// Problems that break the build, red
+pub fn error(text: impl Display) -> String {
+    paint("31", text)
+}
@@ -0,0 +1,174 @@
+//! Cargo configuration files
+//!
+//! Cargo looks for `.cargo/config.toml` (or `.cargo/config`) in the current directory and all
+//! of its parents, then in `CARGO_HOME`. Values from the closest file win.
+
+use std::path::{Path, PathBuf};
+
+/// Existing cargo configuration files affecting `dir`, closest first
+#[must_use]
+pub fn config_files(dir: &Path) -> Vec<PathBuf> {
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".cargo")));
+    let mut res = Vec::new();
+    for dir in dir
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .chain(cargo_home)
+    {
+        for file in ["config.toml", "config"] {
+            let path = dir.join(file);
+            if path.is_file() && !res.contains(&path) {
+                res.push(path);
+            }
+        }
+    }
+    res
+}
+
+/// `[build] target` value from the closest configuration file that sets it
+///
+/// Empty if no configuration file sets it
+pub fn build_targets(dir: &Path) -> anyhow::Result<Vec<String>> {
+    for path in config_files(dir) {
+        let config = std::fs::read_to_string(&path)?.parse::<toml_edit::Document>()?;
+        let Some(target) = config.get("build").and_then(|b| b.get("target")) else {
+            continue;
+        };
+        if let Some(target) = target.as_str() {
+            return Ok(vec![target.to_string()]);
+        } else if let Some(targets) = target.as_array() {
+            return targets
+                .iter()
+                .map(|t| t.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| anyhow::anyhow!("Invalid build.target in {}", path.display()));
+        } else {
+            anyhow::bail!("Invalid build.target in {}", path.display());
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// Targets set by `CARGO_BUILD_TARGET`, it takes priority over configuration files the same
+/// way as for cargo itself
+fn env_targets(value: Option<&str>) -> Option<Vec<String>> {
+    let value = value?.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(vec![value.to_string()])
+    }
+}
+
+/// Targets cargo builds for by default when invoked from `dir`: `CARGO_BUILD_TARGET`,
+/// configured ones or the host
+pub fn default_targets(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let env = std::env::var("CARGO_BUILD_TARGET").ok();
+    let targets = match env_targets(env.as_deref()) {
+        Some(targets) => targets,
+        None => build_targets(dir)?,
+    };
+    if targets.is_empty() {
+        Ok(vec![target_spec::Platform::current()?
+            .triple_str()
+            .to_string()])
+    } else {
+        Ok(targets)
+    }
+}
+
+/// Short names accepted by `--target` instead of full triples
+///
+/// Neither `target-spec` nor `cargo-platform` list builtin targets, so the table is kept by
+/// hand. Tests check that every triple here is known to `target-spec`.
+const TARGET_ALIASES: &[(&str, &[&str])] = &[
+    ("wasm", &["wasm32-unknown-unknown"]),
+    ("wasi", &["wasm32-wasip1"]),
+    ("linux", &["x86_64-unknown-linux-gnu"]),
+    ("windows", &["x86_64-pc-windows-msvc"]),
+    ("macos", &["aarch64-apple-darwin"]),
+    (
+        "unix",
+        &["x86_64-unknown-linux-gnu", "aarch64-apple-darwin"],
+    ),
+];
+
+/// Replace target aliases such as `wasm` or `unix` with triples they stand for
+#[must_use]
+pub fn expand_target_aliases(targets: &[String]) -> Vec<String> {
+    let mut res = Vec::<String>::new();
+    for target in targets {
+        let target = target.as_str();
+        let triples = match TARGET_ALIASES.iter().find(|(alias, _)| *alias == target) {
+            Some((_, triples)) => triples,
+            None => std::slice::from_ref(&target),
+        };
+        for triple in triples {
+            if !res.iter().any(|t| t == triple) {
+                res.push(triple.to_string());
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_targets, env_targets, expand_target_aliases, TARGET_ALIASES};
+
+    #[test]
+    fn build_target_is_read_from_closest_config() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let nested = dir.path().join("nested");
+        std::fs::create_dir_all(dir.path().join(".cargo"))?;
+        std::fs::create_dir_all(nested.join(".cargo"))?;
+        std::fs::write(
+            dir.path().join(".cargo/config.toml"),
+            "[build]\ntarget = \"thumbv7em-none-eabihf\"\n",
+        )?;
+        std::fs::write(
+            nested.join(".cargo/config.toml"),
+            "[build]\ntarget = [\"wasm32-unknown-unknown\", \"x86_64-pc-windows-gnu\"]\n",
+        )?;
+
+        assert_eq!(build_targets(dir.path())?, ["thumbv7em-none-eabihf"]);
+        assert_eq!(
+            build_targets(&nested)?,
+            ["wasm32-unknown-unknown", "x86_64-pc-windows-gnu"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn build_target_env_is_used_when_set() {
+        assert_eq!(env_targets(None), None);
+        assert_eq!(env_targets(Some("")), None);
+        assert_eq!(
+            env_targets(Some("aarch64-apple-darwin")),
+            Some(vec!["aarch64-apple-darwin".to_string()])
+        );
+    }
+
+    #[test]
+    fn target_aliases_are_expanded() {
+        let targets = ["wasm", "x86_64-pc-windows-gnu", "unix", "linux"].map(String::from);
+        assert_eq!(
+            expand_target_aliases(&targets),
+            [
+                "wasm32-unknown-unknown",
+                "x86_64-pc-windows-gnu",
+                "x86_64-unknown-linux-gnu",
+                "aarch64-apple-darwin",
+            ]
+        );
+        for (alias, triples) in TARGET_ALIASES {
+            for triple in *triples {
+                let platform =
+                    target_spec::Platform::new(*triple, target_spec::TargetFeatures::Unknown);
+                assert!(platform.is_ok(), "{alias} stands for unknown {triple}");
+            }
+        }
+    }
+}
@@ -1,15 +1,17 @@
 use crate::{
-    feat_graph::{FeatGraph, HasIndex},
-    metadata::{DepKindInfo, Link},
+    analysis::duplicates,
+    feat_graph::{Feat, FeatGraph, HasIndex, Pid},
+    metadata::{DepKindInfo, DependencyKind, Link},
+    opts::{FeatureSelection, Format},
 };
 
 use petgraph::{
-    graph::NodeIndex,
+    graph::{EdgeIndex, NodeIndex},
     visit::{Dfs, EdgeFiltered, EdgeRef, IntoEdgesDirected, Reversed},
 };
 use semver::Version;
-use std::collections::BTreeSet;
-use tracing::{debug, info};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use tracing::{debug, info, warn};
 
 fn collect_packages(
     fg: &mut FeatGraph,
@@ -17,8 +19,9 @@ fn collect_packages(
     krate: &str,
     feature: Option<&String>,
     version: Option<&Version>,
-) -> Vec<NodeIndex> {
-    fg.features
+) -> anyhow::Result<Vec<NodeIndex>> {
+    let res = fg
+        .features
         .node_indices()
         .filter(|&ix| {
             if let Some(fid) = fg.features[ix].fid() {
@@ -28,14 +31,126 @@ fn collect_packages(
                 // version must match if given
                 package.name == krate
                     && feature.map_or(fid.pid.base() == fid, |f| fid.pid.named(f) == fid)
-                    && version.map_or(true, |v| package.version == *v)
+                    && version.is_none_or(|v| package.version == *v)
             } else {
                 false
             }
         })
+        .collect::<Vec<_>>();
+    if !res.is_empty() {
+        return Ok(res);
+    }
+
+    // explain what is missing exactly
+    let versions = fg
+        .features
+        .node_weights()
+        .filter_map(|f| f.pid())
+        .map(|pid| pid.package())
+        .filter(|package| package.name == krate)
+        .map(|package| &package.version)
+        .collect::<BTreeSet<_>>();
+    if versions.is_empty() {
+        anyhow::bail!("Crate {krate} is not used by the workspace");
+    }
+    let available = versions
+        .iter()
+        .map(ToString::to_string)
         .collect::<Vec<_>>()
+        .join(", ");
+    match (version, feature) {
+        (Some(version), _) if !versions.contains(version) => {
+            anyhow::bail!(
+                "Crate {krate} is present but not at version {version}, available: {available}"
+            )
+        }
+        (_, Some(feature)) => match version {
+            Some(version) => {
+                anyhow::bail!(
+                    "Crate {krate} {version} is present but feature {feature} is not used"
+                )
+            }
+            None => anyhow::bail!("Crate {krate} is present but feature {feature} is not used"),
+        },
+        _ => anyhow::bail!("Can't find crate {krate}, available versions: {available}"),
+    }
 }
 
+/// Feature nodes of workspace members enabled by a cargo style feature selection
+pub(crate) fn selected_features<'a>(
+    fg: &mut FeatGraph<'a>,
+    selection: &FeatureSelection,
+) -> anyhow::Result<Vec<NodeIndex>> {
+    let requested = selection
+        .features
+        .iter()
+        .flat_map(|f| f.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|f| !f.is_empty())
+        .collect::<BTreeSet<_>>();
+    let mut unused = requested.clone();
+
+    let mut res = Vec::new();
+    for member in fg.workspace_members.clone() {
+        let package = member.package();
+        res.push(fg.fid_index(member.base()));
+        for name in package.features.keys() {
+            let by_name = [name.clone(), format!("{}/{name}", package.name)];
+            let wanted = selection.all_features
+                || (name == "default" && !selection.no_default_features)
+                || by_name.iter().any(|n| requested.contains(n.as_str()));
+            if wanted {
+                by_name.iter().for_each(|n| {
+                    unused.remove(n.as_str());
+                });
+                res.push(fg.fid_index(member.named(name)));
+            }
+        }
+    }
+
+    if !selection.all_features && !unused.is_empty() {
+        anyhow::bail!(
+            "None of the workspace members have these features: {}",
+            unused.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    Ok(res)
+}
+
+/// Shrink the graph to the current target keeping everything `selection` enables
+///
+/// Selected member features don't have to be enabled by the whole workspace build, plain
+/// shrinking would drop them along with their dependencies.
+pub(crate) fn shrink_to_selection(
+    fg: &mut FeatGraph,
+    selection: &FeatureSelection,
+) -> anyhow::Result<Vec<NodeIndex>> {
+    let selected = selected_features(fg, selection)?;
+    fg.shrink_to_target_from(&selected)?;
+    selected_features(fg, selection)
+}
+
+/// All the nodes of crates used in more than one version, optionally only for `krate`
+fn duplicated_nodes(fg: &FeatGraph, krate: Option<&str>) -> Vec<NodeIndex> {
+    let packages_in_use = fg
+        .features
+        .node_weights()
+        .filter_map(|f| f.pid())
+        .collect::<BTreeSet<_>>();
+    let duplicated = duplicates(packages_in_use.iter().map(|pid| pid.package()))
+        .into_keys()
+        .filter(|name| krate.is_none_or(|k| k == *name))
+        .collect::<BTreeSet<_>>();
+    fg.features
+        .node_indices()
+        .filter(|&node| {
+            fg.features[node]
+                .pid()
+                .is_some_and(|pid| duplicated.contains(pid.package().name.as_str()))
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn tree<'a>(
     fg: &'a mut FeatGraph<'a>,
     krate: Option<&String>,
@@ -44,19 +159,18 @@ pub fn tree<'a>(
     package_nodes: bool,
     workspace: bool,
     no_dev: bool,
+    optional_only: bool,
+    duplicates_only: bool,
+    selection: &FeatureSelection,
     stdout: bool,
+    format: Format,
 ) -> anyhow::Result<()> {
-    fg.shrink_to_target()?;
-
     let mut packages = match krate {
-        Some(krate) => collect_packages(fg, krate, feature, version),
-        None => {
-            let members = fg.workspace_members.clone();
-            members
-                .iter()
-                .map(|f| fg.fid_index(f.base()))
-                .collect::<Vec<_>>()
+        Some(krate) => {
+            fg.shrink_to_target()?;
+            collect_packages(fg, krate, feature, version)?
         }
+        None => shrink_to_selection(fg, selection)?,
     };
 
     info!("Found {} matching package(s)", packages.len());
@@ -72,6 +186,80 @@ pub fn tree<'a>(
     let mut edges = BTreeSet::new();
     let mut new_edges = BTreeSet::new();
 
+    if duplicates_only {
+        // paths from the workspace to every copy of crates used in several versions
+        debug!("Collecting duplicates");
+        let copies = duplicated_nodes(fg, krate.map(String::as_str));
+        if copies.is_empty() {
+            warn!("All packages are present in one version only");
+        }
+
+        let reversed = Reversed(&g);
+        let mut ancestors = BTreeSet::new();
+        for node in copies {
+            let mut up = Dfs::new(&reversed, node);
+            while let Some(ancestor) = up.next(&reversed) {
+                if fg.features[ancestor].fid().is_some() {
+                    ancestors.insert(ancestor);
+                }
+            }
+        }
+
+        for &node in &ancestors {
+            let this_node = if package_nodes {
+                fg.base_node(node).expect("base node must exist")
+            } else {
+                node
+            };
+            nodes.insert(this_node);
+            for edge in g.edges_directed(node, petgraph::EdgeDirection::Outgoing) {
+                if !ancestors.contains(&edge.target()) {
+                    continue;
+                }
+                if package_nodes {
+                    new_edges.insert((
+                        fg.base_node(edge.target()).expect("base node must exist"),
+                        this_node,
+                    ));
+                } else {
+                    edges.insert(edge.id());
+                }
+            }
+        }
+        packages.clear();
+    }
+
+    if optional_only {
+        // only things behind optional links are interesting: links themselves and everything
+        // reachable from their targets
+        debug!("Collecting optional links");
+        let mut targets = Vec::new();
+        while let Some(next) = packages.pop() {
+            dfs.move_to(next);
+            while let Some(node) = dfs.next(&g) {
+                for edge in g.edges_directed(node, petgraph::EdgeDirection::Outgoing) {
+                    if !edge.weight().optional {
+                        continue;
+                    }
+                    if package_nodes {
+                        let this_node = fg.base_node(node).expect("base node must exist");
+                        nodes.insert(this_node);
+                        new_edges.insert((
+                            fg.base_node(edge.target()).expect("base node must exist"),
+                            this_node,
+                        ));
+                    } else {
+                        nodes.insert(node);
+                        edges.insert(edge.id());
+                    }
+                    targets.push(edge.target());
+                }
+            }
+        }
+        packages = targets;
+        dfs = Dfs::new(&g, fg.root);
+    }
+
     debug!("Collecting dependencies");
     while let Some(next) = packages.pop() {
         dfs.move_to(next);
@@ -113,25 +301,59 @@ pub fn tree<'a>(
 
     fg.focus_nodes = Some(nodes);
     fg.focus_edges = Some(edges);
-    dump_fg(fg, stdout)
+    match format {
+        Format::Dot => dump_fg(fg, stdout),
+        Format::Json => dump_json(fg),
+        Format::Text => {
+            print!("{}", fg.to_text());
+            Ok(())
+        }
+    }
 }
 
+/// Transitive reduction can remove a direct link from a workspace member to the crate (or one
+/// of its features) in favor of a longer path, tell the user how to get it back
+fn hint_reduced_links(fg: &FeatGraph, targets: &[NodeIndex]) {
+    let members = targets
+        .iter()
+        .filter_map(|&ix| fg.features[ix].fid())
+        .flat_map(|target| {
+            fg.reduced_edges.iter().filter(move |(from, to)| {
+                to.pid == target.pid && fg.workspace_members.contains(&from.pid)
+            })
+        })
+        .map(|(from, _)| from.pid.package().name.as_str())
+        .collect::<BTreeSet<_>>();
+    if !members.is_empty() {
+        warn!(
+            "Direct links from {} were hidden by transitive reduction, use -T to keep them",
+            members.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn explain<'a>(
     fg: &'a mut FeatGraph<'a>,
     krate: &str,
     feature: Option<&String>,
     version: Option<&Version>,
     package_nodes: bool,
+    features_only: bool,
     stdout: bool,
+    kind: Option<DependencyKind>,
+    no_dev: bool,
+    until: Option<&str>,
+    format: Format,
 ) -> anyhow::Result<()> {
+    // enabled features are listed on the package nodes instead
+    let package_nodes = package_nodes || features_only;
     fg.shrink_to_target()?;
-    let mut packages = collect_packages(fg, krate, feature, version);
+    let mut packages = collect_packages(fg, krate, feature, version)?;
 
     info!("Found {} matching package(s)", packages.len());
 
-    if packages.is_empty() {
-        anyhow::bail!("Can't find crate {krate} with feature {feature:?} and version {version:?}");
-    }
+    hint_reduced_links(fg, &packages);
 
     if package_nodes {
         fg.focus_targets = Some(
@@ -144,7 +366,18 @@ pub fn explain<'a>(
         fg.focus_targets = Some(packages.iter().copied().collect::<BTreeSet<_>>());
     }
     let g = EdgeFiltered::from_fn(Reversed(&fg.features), |e| {
-        !fg.features[e.source()].is_workspace()
+        // links between features of the same crate are always followed
+        let same_crate = fg.features[e.source()].pid() == fg.features[e.target()].pid();
+        let kind_matches = match kind {
+            None => true,
+            Some(_) if same_crate => true,
+            Some(DependencyKind::Normal) => e.weight().is_normal(),
+            Some(kind) => !fg.features[e.target()].is_workspace() || e.weight().has_kind(kind),
+        };
+        let dev_matches = !(no_dev && e.weight().is_dev_only());
+        // boundary crate is the last one on the way up
+        let boundary = !same_crate && until.is_some_and(|b| is_crate(fg, e.source(), b));
+        !fg.features[e.source()].is_workspace() && kind_matches && dev_matches && !boundary
     });
 
     let mut dfs = Dfs::new(&g, fg.root);
@@ -189,18 +422,340 @@ pub fn explain<'a>(
         }
     }
 
+    if let Some(until) = until {
+        keep_paths_from(fg, until, &mut nodes, &mut edges)?;
+    }
+
+    if features_only {
+        fg.feature_labels = Some(feature_labels(fg, &nodes));
+    }
+
     info!("Done traversing");
     debug!("Found {} nodes and {} edges", nodes.len(), edges.len());
 
     fg.focus_nodes = Some(nodes);
     fg.focus_edges = Some(edges);
-    dump_fg(fg, stdout)
+    match format {
+        Format::Dot => dump_fg(fg, stdout),
+        Format::Json => dump_json(fg),
+        Format::Text => {
+            print!("{}", fg.to_text());
+            Ok(())
+        }
+    }
+}
+
+fn is_crate(fg: &FeatGraph, node: NodeIndex, krate: &str) -> bool {
+    fg.features[node]
+        .pid()
+        .is_some_and(|pid| pid.package().name == krate)
+}
+
+/// Keep only nodes and edges on the paths going from `boundary` crate down through `edges`
+fn keep_paths_from(
+    fg: &FeatGraph,
+    boundary: &str,
+    nodes: &mut BTreeSet<NodeIndex>,
+    edges: &mut BTreeSet<EdgeIndex>,
+) -> anyhow::Result<()> {
+    let mut todo = nodes
+        .iter()
+        .copied()
+        .filter(|&node| is_crate(fg, node, boundary))
+        .collect::<Vec<_>>();
+    if todo.is_empty() {
+        anyhow::bail!("{boundary} is not on the way from the crate to the workspace");
+    }
+    let mut keep = BTreeSet::new();
+    while let Some(node) = todo.pop() {
+        if keep.insert(node) {
+            todo.extend(
+                fg.features
+                    .edges_directed(node, petgraph::EdgeDirection::Outgoing)
+                    .filter(|edge| edges.contains(&edge.id()))
+                    .map(|edge| edge.target()),
+            );
+        }
+    }
+    nodes.retain(|node| keep.contains(node));
+    edges.retain(|&edge| {
+        fg.features
+            .edge_endpoints(edge)
+            .is_some_and(|(a, b)| keep.contains(&a) && keep.contains(&b))
+    });
+    Ok(())
+}
+
+/// Enabled named features of every base node in `nodes`, sorted by name
+fn feature_labels<'a>(
+    fg: &FeatGraph<'a>,
+    nodes: &BTreeSet<NodeIndex>,
+) -> BTreeMap<NodeIndex, Vec<&'a str>> {
+    let mut labels = BTreeMap::<NodeIndex, Vec<&str>>::new();
+    for ix in fg.features.node_indices() {
+        let Some(Feat::Named(name)) = fg.features[ix].fid().map(|fid| fid.dep) else {
+            continue;
+        };
+        if let Some(base) = fg.base_node(ix).filter(|base| nodes.contains(base)) {
+            labels.entry(base).or_default().push(name);
+        }
+    }
+    for feats in labels.values_mut() {
+        feats.sort_unstable();
+    }
+    labels
+}
+
+/// Difference between features declared by two versions of a crate
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FeatureDiff<'a> {
+    /// Features only present in the new version
+    pub added: Vec<&'a str>,
+    /// Features only present in the old version
+    pub removed: Vec<&'a str>,
+    /// Features present in both versions, with old and new contents
+    pub changed: Vec<(&'a str, &'a [String], &'a [String])>,
+}
+
+impl FeatureDiff<'_> {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+fn diff_feature_maps<'a>(
+    old: &'a BTreeMap<String, Vec<String>>,
+    new: &'a BTreeMap<String, Vec<String>>,
+) -> FeatureDiff<'a> {
+    let mut res = FeatureDiff::default();
+    for (name, old_feats) in old {
+        match new.get(name) {
+            Some(new_feats) if new_feats != old_feats => {
+                res.changed.push((name, old_feats, new_feats));
+            }
+            Some(_) => {}
+            None => res.removed.push(name),
+        }
+    }
+    res.added = new
+        .keys()
+        .filter(|name| !old.contains_key(*name))
+        .map(String::as_str)
+        .collect();
+    res
+}
+
+/// Compare features declared by two resolved versions of a crate
+pub fn diff_features<'a>(
+    fg: &mut FeatGraph<'a>,
+    krate: &str,
+    old: &Version,
+    new: &Version,
+) -> anyhow::Result<FeatureDiff<'a>> {
+    let mut package = |version| {
+        let nodes = collect_packages(fg, krate, None, Some(version))?;
+        match nodes.first().and_then(|&ix| fg.features[ix].pid()) {
+            Some(pid) => Ok(pid.package()),
+            None => anyhow::bail!("Can't find crate {krate} with version {version}"),
+        }
+    };
+    let old = package(old)?;
+    let new = package(new)?;
+    Ok(diff_feature_maps(&old.features, &new.features))
+}
+
+/// Shortest feature chains from workspace members to a crate, one per member
+///
+/// Each chain starts at a workspace node and ends at one of the crate nodes
+pub fn dependents(
+    fg: &mut FeatGraph,
+    krate: &str,
+    feature: Option<&String>,
+    version: Option<&Version>,
+) -> anyhow::Result<Vec<Vec<NodeIndex>>> {
+    fg.shrink_to_target()?;
+    let packages = collect_packages(fg, krate, feature, version)?;
+
+    // breadth first search from the crate towards the workspace, every node remembers the
+    // next node on the way to the crate
+    let g = Reversed(&fg.features);
+    let mut next = BTreeMap::new();
+    let mut queue = packages.iter().copied().collect::<VecDeque<_>>();
+    let mut members = BTreeMap::new();
+    while let Some(node) = queue.pop_front() {
+        let feature = fg.features[node];
+        if feature.is_workspace() {
+            if let Some(pid) = feature.pid() {
+                members.entry(pid).or_insert(node);
+            }
+            // chains lead to the nearest member only
+            if !packages.contains(&node) {
+                continue;
+            }
+        }
+        for edge in g.edges_directed(node, petgraph::EdgeDirection::Outgoing) {
+            let parent = edge.target();
+            if parent != fg.root && !packages.contains(&parent) && !next.contains_key(&parent) {
+                next.insert(parent, node);
+                queue.push_back(parent);
+            }
+        }
+    }
+
+    Ok(members
+        .into_values()
+        .map(|mut node| {
+            let mut chain = vec![node];
+            while let Some(&n) = next.get(&node) {
+                chain.push(n);
+                node = n;
+            }
+            chain
+        })
+        .collect())
+}
+
+/// One step in a chain of feature activations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Activation {
+    pub node: NodeIndex,
+    /// Set if the step comes from a weak `dep?/feat` dependency: it only happens if this
+    /// implicit feature for the optional dependency is enabled by something else
+    pub weak_dep: Option<NodeIndex>,
+}
+
+/// Shortest chains of feature activations from workspace members to a named feature of a
+/// crate, one per member
+///
+/// Unlike [`dependents`] chains go through weak `dep?/feat` dependencies as well. Each chain
+/// starts at a workspace node and ends at one of the feature nodes.
+pub fn why_feature(
+    fg: &mut FeatGraph,
+    krate: &str,
+    feature: &str,
+    version: Option<&Version>,
+) -> anyhow::Result<Vec<Vec<Activation>>> {
+    fg.shrink_to_target()?;
+    let packages = collect_packages(fg, krate, Some(&feature.to_string()), version)?;
+
+    // weak dependencies are not edges in the graph, `weak_feat` is enabled by `feature`
+    // as long as `weak_dep` is enabled too
+    let mut weak = BTreeMap::<NodeIndex, Vec<(NodeIndex, NodeIndex)>>::new();
+    for t in &fg.triggers {
+        if let (Some(&feature), Some(&weak_dep), Some(&weak_feat)) = (
+            fg.fid_cache.get(&t.feature),
+            fg.fid_cache.get(&t.weak_dep),
+            fg.fid_cache.get(&t.weak_feat),
+        ) {
+            weak.entry(weak_feat).or_default().push((feature, weak_dep));
+        }
+    }
+
+    // same breadth first search as in `dependents`, every node remembers the next step
+    // on the way to the feature
+    let g = Reversed(&fg.features);
+    let mut next = BTreeMap::new();
+    let mut queue = packages.iter().copied().collect::<VecDeque<_>>();
+    let mut members = BTreeMap::new();
+    while let Some(node) = queue.pop_front() {
+        let feature = fg.features[node];
+        // chains lead to the nearest member only
+        if feature.is_workspace() && !packages.contains(&node) {
+            if let Some(pid) = feature.pid() {
+                members.entry(pid).or_insert(node);
+            }
+            continue;
+        }
+        let parents = g
+            .edges_directed(node, petgraph::EdgeDirection::Outgoing)
+            .map(|edge| (edge.target(), None))
+            .chain(
+                weak.get(&node)
+                    .into_iter()
+                    .flatten()
+                    .map(|&(feature, weak_dep)| (feature, Some(weak_dep))),
+            )
+            .collect::<Vec<_>>();
+        for (parent, weak_dep) in parents {
+            if parent != fg.root && !packages.contains(&parent) && !next.contains_key(&parent) {
+                next.insert(parent, Activation { node, weak_dep });
+                queue.push_back(parent);
+            }
+        }
+    }
+
+    Ok(members
+        .into_values()
+        .map(|mut node| {
+            let mut chain = vec![Activation {
+                node,
+                weak_dep: None,
+            }];
+            while let Some(&step) = next.get(&node) {
+                chain.push(step);
+                node = step.node;
+            }
+            chain
+        })
+        .collect())
+}
+
+/// A named feature and packages that are only used because it is enabled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bloat<'a> {
+    pub node: NodeIndex,
+    pub added: Vec<Pid<'a>>,
+}
+
+/// Enabled named features ranked by how many packages they bring into the build
+///
+/// A package is added by a feature if it is no longer enabled on the current target
+/// once the feature is disabled, workspace members are never counted. Features that add
+/// nothing are not listed, features adding the same number of packages are ordered by name.
+pub fn bloat_features<'a>(fg: &mut FeatGraph<'a>) -> anyhow::Result<Vec<Bloat<'a>>> {
+    fg.shrink_to_target()?;
+    let enabled = fg.enabled_without(None);
+    // workspace members are built regardless of their features
+    let packages = |nodes: &BTreeSet<NodeIndex>| {
+        nodes
+            .iter()
+            .filter(|&&ix| !fg.features[ix].is_workspace())
+            .filter_map(|&ix| fg.features[ix].fid())
+            .filter(|fid| fid.dep == Feat::Base)
+            .map(|fid| fid.pid)
+            .collect::<BTreeSet<_>>()
+    };
+    let all = packages(&enabled);
+
+    let mut res = Vec::new();
+    for &node in &enabled {
+        if !fg.features[node]
+            .fid()
+            .is_some_and(|fid| matches!(fid.dep, Feat::Named(_)))
+        {
+            continue;
+        }
+        let without = packages(&fg.enabled_without(Some(node)));
+        let added = all.difference(&without).copied().collect::<Vec<_>>();
+        if !added.is_empty() {
+            debug!("{} adds {} packages", fg.features[node], added.len());
+            res.push(Bloat { node, added });
+        }
+    }
+    res.sort_by(|a, b| {
+        b.added
+            .len()
+            .cmp(&a.added.len())
+            .then_with(|| fg.features[a.node].cmp(&fg.features[b.node]))
+    });
+    Ok(res)
 }
 
 fn dump_fg(fg: &FeatGraph, stdout: bool) -> anyhow::Result<()> {
     if !stdout {
         let mut file = tempfile::NamedTempFile::new()?;
-        dot::render(fg, &mut file)?;
+        fg.render_dot(&mut file)?;
         if std::process::Command::new("xdot")
             .args([file.path()])
             .output()
@@ -208,9 +763,313 @@ fn dump_fg(fg: &FeatGraph, stdout: bool) -> anyhow::Result<()> {
         {
             return Ok(());
         }
+        warn!("Couldn't run xdot, printing the graph to stdout");
     }
 
-    dot::render(fg, &mut std::io::stdout())?;
+    fg.render_dot(&mut std::io::stdout())?;
 
     Ok(())
 }
+
+fn dump_json(fg: &FeatGraph) -> anyhow::Result<()> {
+    serde_json::to_writer_pretty(std::io::stdout(), &fg.to_json())?;
+    println!();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bloat_features, collect_packages, dependents, diff_feature_maps, duplicated_nodes,
+        feature_labels, keep_paths_from, selected_features, shrink_to_selection, why_feature,
+    };
+    use crate::{
+        feat_graph::{test::get_demo_meta, Feat, FeatGraph},
+        metadata::Target,
+        opts::FeatureSelection,
+    };
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[test]
+    fn weak_dependencies_activate_features() -> anyhow::Result<()> {
+        let meta = get_demo_meta(18)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let chains = why_feature(&mut fg, "gamma", "serde", None)?;
+        let chains = chains
+            .iter()
+            .map(|chain| {
+                chain
+                    .iter()
+                    .map(|step| {
+                        let name = |ix: petgraph::graph::NodeIndex| {
+                            let fid = fg.features[ix].fid().unwrap();
+                            format!("{}:{}", fid.pid.package().name, fid.dep)
+                        };
+                        (name(step.node), step.weak_dep.map(name))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chains,
+            [[
+                ("alpha:serde".to_string(), None),
+                ("gamma:serde".to_string(), Some("alpha:gamma".to_string())),
+            ]]
+        );
+
+        let err = why_feature(&mut fg, "gamma", "std", None).unwrap_err();
+        assert!(err.to_string().contains("gamma"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn features_are_ranked_by_added_packages() -> anyhow::Result<()> {
+        let meta = get_demo_meta(18)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let ranked = bloat_features(&mut fg)?
+            .into_iter()
+            .map(|bloat| {
+                let fid = fg.features[bloat.node].fid().unwrap();
+                let added = bloat
+                    .added
+                    .iter()
+                    .map(|pid| pid.package().name.as_str())
+                    .collect::<Vec<_>>();
+                (format!("{}:{}", fid.pid.package().name, fid.dep), added)
+            })
+            .collect::<Vec<_>>();
+        // alpha:serde only enables gamma:serde, that is not a new package
+        assert_eq!(
+            ranked,
+            [
+                ("alpha:default".to_string(), vec!["gamma"]),
+                ("alpha:gamma".to_string(), vec!["gamma"]),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn enabled_features_label_package_nodes() -> anyhow::Result<()> {
+        let meta = get_demo_meta(18)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        fg.shrink_to_target()?;
+        let nodes = fg
+            .features
+            .node_indices()
+            .filter(|&ix| {
+                fg.features[ix]
+                    .fid()
+                    .is_some_and(|fid| fid.dep == Feat::Base)
+            })
+            .collect::<BTreeSet<_>>();
+        let labels = feature_labels(&fg, &nodes)
+            .into_iter()
+            .map(|(ix, feats)| {
+                (
+                    fg.features[ix].pid().unwrap().package().name.as_str(),
+                    feats,
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(labels["alpha"], ["default", "gamma", "serde"]);
+        // enabled by alpha's weak `gamma?/serde`
+        assert_eq!(labels["gamma"], ["serde"]);
+        assert!(!labels.contains_key("beta"));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_version_lists_available() -> anyhow::Result<()> {
+        let meta = get_demo_meta(12)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let version = semver::Version::new(9, 9, 9);
+        let err = collect_packages(&mut fg, "delta", None, Some(&version)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Crate delta is present but not at version 9.9.9, available: 0.1.0"
+        );
+        let err = collect_packages(&mut fg, "omega", None, None).unwrap_err();
+        assert_eq!(err.to_string(), "Crate omega is not used by the workspace");
+        assert_eq!(collect_packages(&mut fg, "delta", None, None)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn duplicated_crates_are_found() -> anyhow::Result<()> {
+        let meta = get_demo_meta(15)?;
+        let fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let copies = duplicated_nodes(&fg, None)
+            .into_iter()
+            .map(|ix| fg.features[ix].pid().unwrap().package().version.to_string())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(copies, BTreeSet::from(["0.1.0".into(), "0.2.0".into()]));
+        assert_eq!(duplicated_nodes(&fg, Some("omega")).len(), 2);
+        assert!(duplicated_nodes(&fg, Some("zeta")).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn feature_maps_are_compared() {
+        let map = |feats: &[(&str, &[&str])]| {
+            feats
+                .iter()
+                .map(|(name, deps)| {
+                    (
+                        name.to_string(),
+                        deps.iter().map(ToString::to_string).collect(),
+                    )
+                })
+                .collect::<BTreeMap<String, Vec<String>>>()
+        };
+        let old = map(&[("default", &["std"]), ("std", &[]), ("old", &[])]);
+        let new = map(&[("default", &["std", "alloc"]), ("std", &[]), ("alloc", &[])]);
+
+        let diff = diff_feature_maps(&old, &new);
+        assert_eq!(diff.added, ["alloc"]);
+        assert_eq!(diff.removed, ["old"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].0, "default");
+        assert_eq!(diff.changed[0].2, ["std", "alloc"]);
+        assert!(diff_feature_maps(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn tree_starts_from_default_features() -> anyhow::Result<()> {
+        let meta = get_demo_meta(18)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let mut selected = |no_default_features| {
+            let selection = FeatureSelection {
+                no_default_features,
+                ..FeatureSelection::default()
+            };
+            selected_features(&mut fg, &selection).map(|nodes| {
+                nodes
+                    .into_iter()
+                    .map(|ix| {
+                        let fid = fg.features[ix].fid().unwrap();
+                        format!("{}:{}", fid.pid.package().name, fid.dep)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        // members start from what gets built and what `hack` unifies
+        assert_eq!(
+            selected(false)?,
+            ["alpha::base:", "alpha:default", "beta::base:"]
+        );
+        // `--no-default-features` starts from base nodes only
+        assert_eq!(selected(true)?, ["alpha::base:", "beta::base:"]);
+        Ok(())
+    }
+
+    #[test]
+    fn tree_features_follow_cargo_selection() -> anyhow::Result<()> {
+        let meta = get_demo_meta(2)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let mut selected = |features: &[&str], all_features| {
+            let selection = FeatureSelection {
+                features: features.iter().map(ToString::to_string).collect(),
+                all_features,
+                no_default_features: false,
+            };
+            selected_features(&mut fg, &selection).map(|nodes| {
+                nodes
+                    .into_iter()
+                    .map(|ix| {
+                        let fid = fg.features[ix].fid().unwrap();
+                        match fid.dep {
+                            Feat::Base => fid.pid.package().name.clone(),
+                            Feat::Named(name) => format!("{}:{name}", fid.pid.package().name),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(selected(&[], false)?, ["alpha", "beta"]);
+        assert_eq!(
+            selected(&["debug"], false)?,
+            ["alpha", "alpha:debug", "beta", "beta:debug"]
+        );
+        assert_eq!(
+            selected(&["alpha/gamma, alpha/debug"], false)?,
+            ["alpha", "alpha:debug", "alpha:gamma", "beta"]
+        );
+        assert_eq!(
+            selected(&[], true)?,
+            ["alpha", "alpha:debug", "alpha:gamma", "beta", "beta:debug"]
+        );
+        assert!(selected(&["missing"], false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn selected_features_survive_shrinking() -> anyhow::Result<()> {
+        let meta = get_demo_meta(14)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let selection = FeatureSelection {
+            features: vec!["beta/extra".to_string()],
+            ..FeatureSelection::default()
+        };
+        let selected = shrink_to_selection(&mut fg, &selection)?;
+        let name = |ix: petgraph::graph::NodeIndex| {
+            let fid = fg.features[ix].fid().unwrap();
+            format!("{}:{}", fid.pid.package().name, fid.dep)
+        };
+        let extra = selected
+            .iter()
+            .copied()
+            .find(|&ix| name(ix) == "beta:extra")
+            .expect("beta/extra is selected");
+        // the whole workspace build doesn't enable beta/extra, its links must be kept anyway
+        let deps = fg
+            .features
+            .neighbors(extra)
+            .map(name)
+            .collect::<BTreeSet<_>>();
+        assert!(deps.contains("gamma:testing"), "{deps:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn dependents_are_nearest_members() -> anyhow::Result<()> {
+        let meta = get_demo_meta(12)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let chains = dependents(&mut fg, "delta", None, None)?;
+        let members = chains
+            .iter()
+            .map(|chain| {
+                let first = fg.features[chain[0]].pid().unwrap().package();
+                let last = fg.features[*chain.last().unwrap()].pid().unwrap().package();
+                (first.name.as_str(), last.name.as_str())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(members, [("alpha", "delta"), ("beta", "delta")]);
+        Ok(())
+    }
+
+    #[test]
+    fn boundary_keeps_paths_below_it() -> anyhow::Result<()> {
+        let meta = get_demo_meta(13)?;
+        let fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let mut nodes = fg.features.node_indices().collect::<BTreeSet<_>>();
+        let mut edges = fg.features.edge_indices().collect::<BTreeSet<_>>();
+        keep_paths_from(&fg, "epsilon", &mut nodes, &mut edges)?;
+        let names = nodes
+            .iter()
+            .filter_map(|&ix| fg.features[ix].pid())
+            .map(|pid| pid.package().name.as_str())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(names, BTreeSet::from(["epsilon", "zeta"]));
+        for edge in edges {
+            let (a, b) = fg.features.edge_endpoints(edge).unwrap();
+            assert!(nodes.contains(&a) && nodes.contains(&b));
+        }
+
+        let mut nodes = fg.features.node_indices().collect::<BTreeSet<_>>();
+        assert!(keep_paths_from(&fg, "omega", &mut nodes, &mut BTreeSet::new()).is_err());
+        Ok(())
+    }
+}
@@ -1,15 +1,14 @@
 use crate::hack::Collect;
-use crate::metadata::{DepKindInfo, Link};
+use crate::metadata::{DepKindInfo, DependencyKind, Link, Target};
 use cargo_metadata::{Metadata, Package, PackageId, Source};
-use cargo_platform::Cfg;
 use dot::{GraphWalk, Labeller};
-use petgraph::graph::{EdgeIndex, NodeIndex};
+use petgraph::graph::{EdgeIndex, EdgeReference, NodeIndex};
 use petgraph::visit::{Dfs, EdgeFiltered, EdgeRef};
 use petgraph::Graph;
 use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
 use std::ops::Index;
-use tracing::{debug, error, info, trace};
+use tracing::{debug, info, trace, warn};
 
 #[derive(Copy, Clone, Ord, PartialEq, Eq, PartialOrd, Debug)]
 /// An node for feature graph
@@ -78,13 +77,27 @@ pub struct FeatGraph<'a> {
     /// cargo metadata
     meta: &'a Metadata,
 
-    pub platforms: Vec<&'a str>,
-    pub cfgs: Vec<Cfg>,
+    /// targets to check target specific dependencies against, any of them can enable a link
+    pub targets: Vec<Target>,
     pub triggers: Vec<Trigger<'a>>,
 
     pub focus_nodes: Option<BTreeSet<NodeIndex>>,
     pub focus_edges: Option<BTreeSet<EdgeIndex>>,
     pub focus_targets: Option<BTreeSet<NodeIndex>>,
+    /// enabled features to list on base nodes when each crate is shown as a single node
+    pub feature_labels: Option<BTreeMap<NodeIndex, Vec<&'a str>>>,
+
+    /// links removed by transitive reduction, node indices don't survive further graph changes
+    pub reduced_edges: BTreeSet<(Fid<'a>, Fid<'a>)>,
+
+    /// extra graph level attributes for dot output, such as `rankdir`
+    pub graph_attrs: Vec<(String, String)>,
+    /// default node attributes for dot output, such as `fontname`
+    pub node_attrs: Vec<(String, String)>,
+
+    /// packages that replace dependencies from another source with `[patch]` or `[replace]`,
+    /// mapped to the source the dependency is declared with
+    pub patched: BTreeMap<Pid<'a>, &'a str>,
 }
 
 impl<'a> Index<Pid<'a>> for FeatGraph<'a> {
@@ -144,33 +157,111 @@ impl<'a> FeatGraph<'a> {
             .copied()
     }
 
+    /// Check if a link should be followed when collecting features with `filter`
+    ///
+    /// Filters that are limited to the current target follow links used by any of the graph
+    /// targets.
+    #[must_use]
+    pub fn follows(&self, e: EdgeReference<'_, Link>, filter: Collect) -> bool {
+        match filter {
+            Collect::AllTargets => true,
+            Collect::Target | Collect::NoDev | Collect::DevTarget | Collect::MemberDev(_) => e
+                .weight()
+                .satisfies(self.features[e.source()], filter, &self.targets),
+            Collect::NormalOnly => e.weight().is_normal(),
+            Collect::NormalAndMemberDev => {
+                e.weight().is_normal()
+                    || (self.features[e.source()].is_workspace()
+                        && e.weight().has_kind(DependencyKind::Development))
+            }
+            Collect::MemberNorm(pid) | Collect::MemberBuild(pid) => {
+                // links from the member itself to other crates must be of the right kind
+                let kind = match filter {
+                    Collect::MemberBuild(_) => DependencyKind::Build,
+                    _ => DependencyKind::Normal,
+                };
+                let leaves_member = self.features[e.source()].pid() == Some(pid)
+                    && self.features[e.target()].pid() != Some(pid);
+                (!leaves_member || e.weight().kinds.iter().any(|k| k.kind == kind))
+                    && e.weight()
+                        .satisfies(self.features[e.source()], filter, &self.targets)
+            }
+        }
+    }
+
+    /// Check if `to` can be reached from `from` following only links allowed by `filter`
+    ///
+    /// For example `Collect::MemberNorm(pid)` checks if there's a path that uses only normal
+    /// dependencies of the member on the graph target.
+    #[must_use]
+    pub fn reachable(&self, from: NodeIndex, to: NodeIndex, filter: Collect) -> bool {
+        let g = EdgeFiltered::from_fn(&self.features, |e| self.follows(e, filter));
+        petgraph::algo::has_path_connecting(&g, from, to, None)
+    }
+
     pub fn shrink_to_target(&mut self) -> anyhow::Result<()> {
+        self.shrink_to_target_from(&[])
+    }
+
+    /// Same as [`shrink_to_target`](Self::shrink_to_target), but also keeps everything
+    /// enabled by `extra` nodes, such as member features not enabled by default
+    ///
+    /// Node indices are not preserved, look the nodes up again after shrinking
+    pub fn shrink_to_target_from(&mut self, extra: &[NodeIndex]) -> anyhow::Result<()> {
         info!("Shrinking to current target");
+        let this = self.enabled_from(extra, None);
+        self.features.retain_nodes(|_, ix| this.contains(&ix));
+        self.rebuild_cache()?;
+
+        Ok(())
+    }
+
+    /// Nodes enabled on the current target, pretending `skip` is never enabled
+    ///
+    /// Follows weak dependencies as well: they are not edges in the graph
+    #[must_use]
+    pub fn enabled_without(&self, skip: Option<NodeIndex>) -> BTreeSet<NodeIndex> {
+        self.enabled_from(&[], skip)
+    }
+
+    fn enabled_from(&self, extra: &[NodeIndex], skip: Option<NodeIndex>) -> BTreeSet<NodeIndex> {
         let g = EdgeFiltered::from_fn(&self.features, |e| {
-            e.weight().satisfies(
-                self.features[e.source()],
-                Collect::DevTarget,
-                &self.platforms,
-                &self.cfgs,
-            )
+            Some(e.target()) != skip
+                && e.weight().satisfies(
+                    self.features[e.source()],
+                    Collect::DevTarget,
+                    &self.targets,
+                )
         });
         let mut dfs = Dfs::new(&g, self.root);
         let mut this = BTreeSet::new();
-        while let Some(ix) = dfs.next(&g) {
-            this.insert(ix);
+        loop {
+            while let Some(ix) = dfs.next(&g) {
+                this.insert(ix);
+            }
+            // features enabled by weak dependencies are reachable as long as both the
+            // feature and the dependency are
+            let seed = extra.iter().copied().find(|ix| !this.contains(ix));
+            let fired = seed.or_else(|| {
+                self.triggers.iter().find_map(|t| {
+                    let weak_feat = *self.fids.get(&t.weak_feat)?;
+                    let reachable = |fid| self.fids.get(&fid).is_some_and(|ix| this.contains(ix));
+                    (Some(weak_feat) != skip
+                        && !this.contains(&weak_feat)
+                        && reachable(t.feature)
+                        && reachable(t.weak_dep))
+                    .then_some(weak_feat)
+                })
+            });
+            match fired {
+                Some(next) => dfs.move_to(next),
+                None => break,
+            }
         }
-
-        self.features.retain_nodes(|_, ix| this.contains(&ix));
-        self.rebuild_cache()?;
-
-        Ok(())
+        this
     }
 
-    pub fn init(
-        meta: &'a Metadata,
-        platforms: Vec<&'a str>,
-        cfgs: Vec<Cfg>,
-    ) -> anyhow::Result<Self> {
+    pub fn init(meta: &'a Metadata, targets: Vec<Target>) -> anyhow::Result<Self> {
         if meta.resolve.is_none() {
             anyhow::bail!("Cargo couldn't produce resolved dependencies")
         }
@@ -194,35 +285,60 @@ impl<'a> FeatGraph<'a> {
                 .collect::<BTreeSet<_>>(),
             features,
             root,
-            platforms,
+            targets,
             fids: BTreeMap::new(),
             triggers: Vec::new(),
             fid_cache: BTreeMap::new(),
             cache,
             meta,
-            cfgs,
             focus_nodes: None,
             focus_edges: None,
             focus_targets: None,
+            feature_labels: None,
+            reduced_edges: BTreeSet::new(),
+            graph_attrs: Vec::new(),
+            node_attrs: Vec::new(),
+            patched: BTreeMap::new(),
         };
+        (graph.graph_attrs, graph.node_attrs) = graph_config(&meta.workspace_metadata);
 
         for (ix, package) in meta.packages.iter().enumerate() {
             graph.add_package(ix, package, &meta.packages)?;
         }
 
         graph.rebuild_cache()?;
+        let (nodes, edges) = graph.size();
+        info!("Feature graph after init: {nodes} nodes, {edges} edges");
 
         Ok(graph)
     }
 
+    /// Number of nodes and edges in the graph
+    #[must_use]
+    pub fn size(&self) -> (usize, usize) {
+        (self.features.node_count(), self.features.edge_count())
+    }
+
     pub fn optimize(&mut self, no_transitive: bool) -> anyhow::Result<()> {
+        let initial = self.size();
         info!("Optimization pass: trim unused features");
         self.trim_unused_features();
+        let trimmed = self.size();
 
+        let mut summary = format!(
+            "Feature graph size: {} nodes, {} edges initially; {} nodes, {} edges after trimming",
+            initial.0, initial.1, trimmed.0, trimmed.1
+        );
         if !no_transitive {
             info!("Optimization pass: transitive reduction");
             self.transitive_reduction();
+            let reduced = self.size();
+            summary += &format!(
+                "; {} nodes, {} edges after transitive reduction",
+                reduced.0, reduced.1
+            );
         }
+        info!("{summary}");
 
         self.rebuild_cache()?;
         Ok(())
@@ -244,26 +360,62 @@ impl<'a> FeatGraph<'a> {
         Ok(())
     }
 
+    /// Remove links implied by other links
+    ///
+    /// Cycles are legal, links inside of them are kept as is while links between them and the
+    /// rest of the graph are reduced on the graph where every cycle is condensed into one node
     fn transitive_reduction(&mut self) {
         use petgraph::algo::tred::dag_to_toposorted_adjacency_list;
         let graph = &mut self.features;
         let before = graph.edge_count();
-        let toposort = match petgraph::algo::toposort(&*graph, None) {
-            Ok(t) => t,
-            Err(err) => {
-                error!("Cyclic dependencies are detected {err:?}, skipping transitive reduction");
-                return;
+
+        let sccs = petgraph::algo::tarjan_scc(&*graph);
+        let mut component = vec![NodeIndex::end(); graph.node_count()];
+        let mut condensed = Graph::<(), ()>::with_capacity(sccs.len(), 0);
+        let mut cyclic = 0;
+        for scc in &sccs {
+            let ix = condensed.add_node(());
+            for node in scc {
+                component[node.index()] = ix;
             }
-        };
+            if scc.len() > 1 || graph.find_edge(scc[0], scc[0]).is_some() {
+                cyclic += 1;
+            }
+        }
+        for edge in graph.edge_references() {
+            let (a, b) = (
+                component[edge.source().index()],
+                component[edge.target().index()],
+            );
+            if a != b {
+                condensed.update_edge(a, b, ());
+            }
+        }
+        if cyclic > 0 {
+            warn!(
+                "Found {cyclic} dependency cycle(s), links inside of them are not reduced. \
+                Use `cargo hackerman cycles` to list them"
+            );
+        }
 
+        let toposort =
+            petgraph::algo::toposort(&condensed, None).expect("condensed graph has no cycles");
         let (adj_list, revmap) =
-            dag_to_toposorted_adjacency_list::<_, NodeIndex>(&*graph, &toposort);
+            dag_to_toposorted_adjacency_list::<_, NodeIndex>(&condensed, &toposort);
         let (reduction, _closure) =
             petgraph::algo::tred::dag_transitive_reduction_closure(&adj_list);
 
+        let reduced_edges = &mut self.reduced_edges;
         graph.retain_edges(|x, y| {
             if let Some((f, t)) = x.edge_endpoints(y) {
-                reduction.contains_edge(revmap[f.index()], revmap[t.index()])
+                let (a, b) = (component[f.index()], component[t.index()]);
+                let keep = a == b || reduction.contains_edge(revmap[a.index()], revmap[b.index()]);
+                if !keep {
+                    if let (Some(f), Some(t)) = (x[f].fid(), x[t].fid()) {
+                        reduced_edges.insert((f, t));
+                    }
+                }
+                keep
             } else {
                 false
             }
@@ -272,6 +424,28 @@ impl<'a> FeatGraph<'a> {
         debug!("Transitive reduction, edges {before} -> {after}");
     }
 
+    /// Strongly connected components with more than one node or with a self loop
+    ///
+    /// Nodes inside of each cycle are sorted by their feature ids, cycles are sorted by their
+    /// first node
+    #[must_use]
+    pub fn cycles(&self) -> Vec<Vec<NodeIndex>> {
+        let mut cycles = petgraph::algo::tarjan_scc(&self.features)
+            .into_iter()
+            .filter(|scc| match scc.as_slice() {
+                [] => false,
+                [node] => self.features.find_edge(*node, *node).is_some(),
+                _ => true,
+            })
+            .map(|mut scc| {
+                scc.sort_by_key(|&ix| self.features[ix]);
+                scc
+            })
+            .collect::<Vec<_>>();
+        cycles.sort_by_key(|scc| self.features[scc[0]]);
+        cycles
+    }
+
     /// Remove features not used by the workspace directly or indirectly
     ///
     /// should only be used for displaying
@@ -309,6 +483,13 @@ impl<'a> FeatGraph<'a> {
             self.add_edge(self.root, this, false, DepKindInfo::NORMAL)?;
         }
 
+        let resolved_deps = self
+            .meta
+            .resolve
+            .as_ref()
+            .and_then(|r| r.nodes.iter().find(|n| n.id == package.id))
+            .map_or(&[][..], |n| n.deps.as_slice());
+
         // resolve and cache crate dependencies and create a cache mapping name to dep
         let mut deps = BTreeMap::new();
         for dep in &package.dependencies {
@@ -320,7 +501,7 @@ impl<'a> FeatGraph<'a> {
             let source_matches = |a: Option<&Source>, b: Option<&String>| match (a, b) {
                 (None, None) => true,
                 (Some(a), Some(b)) => {
-                    if &a.repr == b || (a.repr.starts_with("git") && a.repr.starts_with(b)) {
+                    if same_source(&a.repr, b) {
                         true
                     } else {
                         trace!("ignoring a candidate {package:?} for {dep:?} due to source mismatch: {a:?} != {b:?}");
@@ -329,23 +510,85 @@ impl<'a> FeatGraph<'a> {
                 }
                 _ => false,
             };
-            // get resolved package - should be there in at most one matching copy...
-            let resolved = match packages.iter().find(|p| {
-                p.name == dep.name
-                    && dep.req.matches(&p.version)
-                    && source_matches(p.source.as_ref(), dep.source.as_ref())
-            }) {
+            // `dep.name` is the name of the crate itself even if it is renamed
+            let matching = packages
+                .iter()
+                .filter(|p| p.name == dep.name && dep.req.matches(&p.version))
+                .collect::<Vec<_>>();
+            // cargo's resolve knows where the package comes from, including dependencies
+            // overridden with `[patch]` or `[replace]` that don't match the declared source.
+            // Dependencies missing from the resolve, such as disabled optional ones, fall back
+            // to comparing sources
+            let mut candidates = matching
+                .iter()
+                .copied()
+                .filter(|p| resolved_deps.iter().any(|d| d.pkg == p.id))
+                .collect::<Vec<_>>();
+            if candidates.len() > 1 {
+                let by_source = candidates
+                    .iter()
+                    .copied()
+                    .filter(|p| source_matches(p.source.as_ref(), dep.source.as_ref()))
+                    .collect::<Vec<_>>();
+                if !by_source.is_empty() {
+                    candidates = by_source;
+                }
+            } else if candidates.is_empty() {
+                candidates = matching
+                    .into_iter()
+                    .filter(|p| source_matches(p.source.as_ref(), dep.source.as_ref()))
+                    .collect();
+            }
+            // get resolved package - usually there's only one matching copy, if not -
+            // cargo's resolve knows which one is used under which name
+            let resolved = match candidates.as_slice() {
+                [] => None,
+                [single] => Some(*single),
+                many => {
+                    let extern_name = dep.rename.as_ref().map(|r| r.replace('-', "_"));
+                    many.iter()
+                        .find(|p| {
+                            resolved_deps.iter().any(|d| {
+                                d.pkg == p.id
+                                    && extern_name.as_ref().is_none_or(|name| &d.name == name)
+                            })
+                        })
+                        .or(many.first())
+                        .copied()
+                }
+            };
+            let resolved = match resolved {
                 Some(res) => res,
-                None => {
+                None if dep.optional => {
                     debug!(
-                        "cargo metadta did not include optional dependency \"{} {}\" \
+                        "cargo metadata did not include optional dependency \"{} {}\" \
                         requested by \"{} {}\", skipping",
                         dep.name, dep.req, package.name, package.version
                     );
                     continue;
                 }
+                None => anyhow::bail!(
+                    "Couldn't find a resolved package for required dependency \"{} {}\" \
+                    requested by \"{} {}\"",
+                    dep.name,
+                    dep.req,
+                    package.name,
+                    package.version
+                ),
             };
 
+            if let Some(declared) = dep.source.as_ref().filter(|declared| {
+                !resolved
+                    .source
+                    .as_ref()
+                    .is_some_and(|src| same_source(&src.repr, declared))
+            }) {
+                if let Some(&pid) = self.cache.get(&resolved.id) {
+                    debug!("{pid:?} replaces {} from {declared}", dep.name);
+                    self.patched.insert(pid, declared.as_str());
+                }
+            }
+
             // feature dependencies:
             //
             // - optional dependencies are linked from named feature
@@ -391,8 +634,13 @@ impl<'a> FeatGraph<'a> {
                         self.add_edge(feat_ix, this.named(name), false, DepKindInfo::NORMAL)?;
                     }
                     FeatTarget::Dependency { krate } => {
-                        if let Some(&(_dep, link, remote)) = deps.get(krate) {
+                        if let Some(&(dep, link, remote)) = deps.get(krate) {
                             self.add_edge(feat_ix, remote, true, link.into())?;
+                            // with `dep:` syntax there might be no implicit feature for the
+                            // dependency to carry features it is imported with
+                            for feat in &link.features {
+                                self.add_edge(feat_ix, (dep, feat.as_str()), true, link.into())?;
+                            }
                         } else {
                             debug!("skipping disabled optional dependency {krate}");
                         }
@@ -561,6 +809,230 @@ pub enum Feat<'a> {
     Named(&'a str),
 }
 
+impl FeatGraph<'_> {
+    /// Export the graph as json, only focused nodes and edges are exported if focus is set
+    ///
+    /// Nodes are objects with `id`, `name`, `version`, `feature` (`null` for the base package)
+    /// and `workspace` fields, edges refer to nodes by `id` and carry `optional` flag along with
+    /// the list of dependency `kinds`. Synthetic root node is not exported.
+    #[must_use]
+    pub fn to_json(&self) -> serde_json::Value {
+        let nodes = self
+            .nodes()
+            .iter()
+            .filter_map(|&ix| {
+                let fid = self.features[ix].fid()?;
+                let package = fid.pid.package();
+                let feature = match fid.dep {
+                    Feat::Base => None,
+                    Feat::Named(name) => Some(name),
+                };
+                let mut node = serde_json::json!({
+                    "id": ix.index(),
+                    "name": package.name,
+                    "version": package.version.to_string(),
+                    "feature": feature,
+                    "workspace": self.features[ix].is_workspace(),
+                });
+                if let Some(feats) = self.feature_labels.as_ref().and_then(|l| l.get(&ix)) {
+                    node["features"] = serde_json::json!(feats);
+                }
+                Some(node)
+            })
+            .collect::<Vec<_>>();
+
+        let edges = self
+            .edges()
+            .iter()
+            .filter_map(|&ix| {
+                let (from, to) = self.features.edge_endpoints(ix)?;
+                if from == self.root {
+                    return None;
+                }
+                let link = &self.features[ix];
+                let kinds = link
+                    .kinds
+                    .iter()
+                    .map(|k| {
+                        serde_json::json!({
+                            "kind": k.kind.as_str(),
+                            "target": k.target.as_ref().map(ToString::to_string),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                Some(serde_json::json!({
+                    "from": from.index(),
+                    "to": to.index(),
+                    "optional": link.optional,
+                    "kinds": kinds,
+                }))
+            })
+            .collect::<Vec<_>>();
+
+        serde_json::json!({ "nodes": nodes, "edges": edges })
+    }
+
+    /// Focused part of the graph as text, every node is followed by an indented list of nodes
+    /// it links to
+    ///
+    /// Base nodes are rendered as `crate name version`, named features as
+    /// `feature name version:feature`, same as octagon and round nodes in the dot output.
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        let describe = |ix: NodeIndex| {
+            let fid = self.features[ix].fid()?;
+            let package = fid.pid.package();
+            let member = if self.features[ix].is_workspace() {
+                " (member)"
+            } else {
+                ""
+            };
+            Some(match fid.dep {
+                Feat::Base => match self.feature_labels.as_ref().and_then(|l| l.get(&ix)) {
+                    Some(feats) => format!(
+                        "crate {} {} [{}]{member}",
+                        package.name,
+                        package.version,
+                        feats.join(", ")
+                    ),
+                    None => format!("crate {} {}{member}", package.name, package.version),
+                },
+                Feat::Named(name) => {
+                    format!(
+                        "feature {} {}:{name}{member}",
+                        package.name, package.version
+                    )
+                }
+            })
+        };
+        let in_focus = |edge| self.focus_edges.as_ref().is_none_or(|f| f.contains(&edge));
+        let mut res = String::new();
+        for &node in self.nodes().iter() {
+            let Some(this) = describe(node) else {
+                continue;
+            };
+            res += &this;
+            res.push('\n');
+            let mut edges = self
+                .features
+                .edges(node)
+                .map(|e| e.id())
+                .filter(|&edge| in_focus(edge))
+                .collect::<Vec<_>>();
+            edges.sort_unstable();
+            for edge in edges {
+                let (_, to) = self.features.edge_endpoints(edge).expect("edge must exist");
+                let Some(target) = describe(to) else {
+                    continue;
+                };
+                let link = &self.features[edge];
+                let dev = if link.is_dev_only() { " (dev)" } else { "" };
+                let optional = if link.optional { " (optional)" } else { "" };
+                res += &format!("    -> {target}{dev}{optional}\n");
+            }
+        }
+        res
+    }
+}
+
+impl FeatGraph<'_> {
+    /// Render the graph in dot format, including extra graph attributes
+    pub fn render_dot<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let mut buf = Vec::new();
+        dot::render(self, &mut buf)?;
+        // `dot` crate has no way to specify graph attributes, they go right after the header
+        let header = buf
+            .iter()
+            .position(|&c| c == b'\n')
+            .map_or(buf.len(), |p| p + 1);
+        w.write_all(&buf[..header])?;
+        for (name, value) in &self.graph_attrs {
+            writeln!(w, "    {name}={};", quote(value))?;
+        }
+        if !self.node_attrs.is_empty() {
+            let attrs = self
+                .node_attrs
+                .iter()
+                .map(|(name, value)| format!("{name}={}", quote(value)))
+                .collect::<Vec<_>>();
+            writeln!(w, "    node [{}];", attrs.join(", "))?;
+        }
+        // nodes mentioned in a cluster are drawn inside of its box, the cluster goes right
+        // before the closing brace
+        let footer = buf
+            .iter()
+            .rposition(|&c| c == b'}')
+            .filter(|&p| p >= header)
+            .unwrap_or(buf.len());
+        w.write_all(&buf[header..footer])?;
+        let members = self
+            .nodes()
+            .iter()
+            .filter(|&&ix| matches!(self.features[ix], Feature::Workspace(_)))
+            .map(|ix| format!("n{}", ix.index()))
+            .collect::<Vec<_>>();
+        if !members.is_empty() {
+            writeln!(w, "    subgraph cluster_workspace {{")?;
+            writeln!(w, "        label=\"workspace\";")?;
+            writeln!(w, "        {};", members.join("; "))?;
+            writeln!(w, "    }}")?;
+        }
+        w.write_all(&buf[footer..])
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\\\""))
+}
+
+type DotAttrs = Vec<(String, String)>;
+
+/// Attributes from `[workspace.metadata.hackerman.graph]` that apply to the graph itself
+const GRAPH_KEYS: &[&str] = &[
+    "bgcolor", "fontname", "fontsize", "nodesep", "pad", "rankdir", "ranksep", "ratio", "size",
+    "splines",
+];
+
+/// Attributes from `[workspace.metadata.hackerman.graph]` that apply to all the nodes
+const NODE_KEYS: &[&str] = &["fontname", "fontsize"];
+
+/// Graph and node level dot attributes configured in `[workspace.metadata.hackerman.graph]`
+fn graph_config(meta: &serde_json::Value) -> (DotAttrs, DotAttrs) {
+    let mut graph = Vec::new();
+    let mut node = Vec::new();
+    let Some(config) = meta.get("hackerman").and_then(|h| h.get("graph")) else {
+        return (graph, node);
+    };
+    let Some(config) = config.as_object() else {
+        warn!("Ignoring workspace.metadata.hackerman.graph, expected a table");
+        return (graph, node);
+    };
+    for (key, value) in config {
+        let value = match value {
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Number(n) => n.to_string(),
+            serde_json::Value::Bool(b) => b.to_string(),
+            _ => {
+                warn!("Ignoring graph attribute {key:?}, expected a string or a number");
+                continue;
+            }
+        };
+        let known_graph = GRAPH_KEYS.contains(&key.as_str());
+        let known_node = NODE_KEYS.contains(&key.as_str());
+        if !(known_graph || known_node) {
+            warn!("Ignoring unknown graph attribute {key:?}");
+            continue;
+        }
+        if known_graph {
+            graph.push((key.clone(), value.clone()));
+        }
+        if known_node {
+            node.push((key.clone(), value));
+        }
+    }
+    (graph, node)
+}
+
 impl<'a> GraphWalk<'a, NodeIndex, EdgeIndex> for FeatGraph<'a> {
     fn nodes(&'a self) -> dot::Nodes<'a, NodeIndex> {
         Cow::from(match &self.focus_nodes {
@@ -617,7 +1089,12 @@ impl<'a> Labeller<'a, NodeIndex, EdgeIndex> for FeatGraph<'a> {
                     }
                 }
                 match fid.dep {
-                    Feat::Base => {}
+                    Feat::Base => {
+                        if let Some(feats) = self.feature_labels.as_ref().and_then(|l| l.get(n)) {
+                            fmt.push('\n');
+                            fmt.push_str(&feats.join(", "));
+                        }
+                    }
                     Feat::Named(name) => {
                         fmt.push('\n');
                         fmt.push_str(name);
@@ -753,6 +1230,20 @@ impl<'a> From<&'a str> for FeatTarget<'a> {
     }
 }
 
+/// Check if package source as reported by cargo matches dependency source
+///
+/// For git dependencies package source also contains a commit hash after `#`, but the rest
+/// of it should be the same
+fn same_source(package: &str, dep: &str) -> bool {
+    if package == dep {
+        return true;
+    }
+    match package.split_once('#') {
+        Some((source, _rev)) => package.starts_with("git+") && source == dep,
+        None => false,
+    }
+}
+
 impl Fid<'_> {
     #[must_use]
     /// Create a base feature from possibly named one
@@ -765,8 +1256,9 @@ impl Fid<'_> {
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
+    use crate::metadata::Resolver;
     #[test]
     fn feat_target() {
         use FeatTarget::*;
@@ -793,7 +1285,166 @@ mod test {
         );
     }
 
-    fn get_demo_meta(ix: usize) -> anyhow::Result<Metadata> {
+    #[test]
+    fn git_sources_match_exactly() {
+        let dep = "git+https://github.com/rust-lang/cargo.git?branch=main";
+        assert!(same_source(
+            "git+https://github.com/rust-lang/cargo.git?branch=main#0227f048",
+            dep
+        ));
+        assert!(same_source(dep, dep));
+        assert!(!same_source(
+            "git+https://github.com/rust-lang/cargo.git?branch=maint#0227f048",
+            dep
+        ));
+        assert!(!same_source(
+            "git+https://github.com/rust-lang/cargo.git#0227f048",
+            dep
+        ));
+        assert!(!same_source(
+            "registry+https://github.com/rust-lang/crates.io-index",
+            "registry+https://example.com/index"
+        ));
+    }
+
+    #[test]
+    fn missing_required_dependency_is_an_error() -> anyhow::Result<()> {
+        let mut meta = get_demo_meta(12)?;
+        let member = meta
+            .packages
+            .iter()
+            .find(|p| meta.workspace_members.contains(&p.id) && !p.dependencies.is_empty())
+            .expect("fixture has members with dependencies");
+        let dep = member
+            .dependencies
+            .iter()
+            .find(|d| !d.optional)
+            .expect("fixture has required dependencies")
+            .name
+            .clone();
+        meta.packages.retain(|p| p.name != dep);
+
+        let err = FeatGraph::init(&meta, Vec::new())
+            .err()
+            .expect("missing dependency must be an error");
+        assert!(err.to_string().contains(&format!("\"{dep} ")), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn graph_attrs_follow_dot_header() -> anyhow::Result<()> {
+        let meta = get_demo_meta(2)?;
+        let mut fg = FeatGraph::init(&meta, Vec::new())?;
+        fg.graph_attrs = vec![("rankdir".to_string(), "LR".to_string())];
+        let mut out = Vec::new();
+        fg.render_dot(&mut out)?;
+        let out = String::from_utf8(out)?;
+        let mut lines = out.lines();
+        assert_eq!(lines.next(), Some("digraph graphname {"));
+        assert_eq!(lines.next(), Some("    rankdir=\"LR\";"));
+        assert_eq!(lines.last(), Some("}"));
+        Ok(())
+    }
+
+    #[test]
+    fn shrinking_keeps_features_enabled_by_weak_dependencies() -> anyhow::Result<()> {
+        let meta = get_demo_meta(18)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let gamma = fg
+            .features
+            .node_indices()
+            .find_map(|ix| {
+                fg.features[ix]
+                    .pid()
+                    .filter(|p| p.package().name == "gamma")
+            })
+            .unwrap();
+        fg.shrink_to_target()?;
+        // alpha enables gamma along with "gamma?/serde", there's no edge to "serde" of gamma
+        assert!(fg.fid_cache.contains_key(&gamma.named("serde")));
+        Ok(())
+    }
+
+    #[test]
+    fn workspace_members_are_clustered() -> anyhow::Result<()> {
+        let meta = get_demo_meta(18)?;
+        let fg = FeatGraph::init(&meta, Vec::new())?;
+        let mut out = Vec::new();
+        fg.render_dot(&mut out)?;
+        let out = String::from_utf8(out)?;
+        let cluster = out
+            .split_once("    subgraph cluster_workspace {\n")
+            .and_then(|(_, rest)| rest.split_once("    }\n"))
+            .map(|(cluster, rest)| {
+                assert_eq!(rest, "}\n");
+                cluster
+            })
+            .unwrap();
+        let members = fg
+            .features
+            .node_indices()
+            .filter(|&ix| matches!(fg.features[ix], Feature::Workspace(_)))
+            .map(|ix| format!("n{}", ix.index()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cluster,
+            format!(
+                "        label=\"workspace\";\n        {};\n",
+                members.join("; ")
+            )
+        );
+        assert!(!members.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn graph_config_sets_graph_and_node_defaults() -> anyhow::Result<()> {
+        let mut meta = get_demo_meta(2)?;
+        meta.workspace_metadata = serde_json::json!({
+            "hackerman": { "graph": { "fontname": "Fira Sans", "nodesep": 0.5, "shiny": true } }
+        });
+        let fg = FeatGraph::init(&meta, Vec::new())?;
+        let mut out = Vec::new();
+        fg.render_dot(&mut out)?;
+        let out = String::from_utf8(out)?;
+        let lines = out.lines().take(4).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "digraph graphname {",
+                "    fontname=\"Fira Sans\";",
+                "    nodesep=\"0.5\";",
+                "    node [fontname=\"Fira Sans\"];",
+            ]
+        );
+        assert!(!out.contains("shiny"));
+        Ok(())
+    }
+
+    /// Copy demo workspaces into a temporary directory, keeping relative paths between them
+    pub(crate) fn copy_demo_workspace(names: &[&str]) -> anyhow::Result<tempfile::TempDir> {
+        fn copy_dir(from: &std::path::Path, to: &std::path::Path) -> std::io::Result<()> {
+            std::fs::create_dir_all(to)?;
+            for entry in std::fs::read_dir(from)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    copy_dir(&entry.path(), &to.join(entry.file_name()))?;
+                } else {
+                    std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+                }
+            }
+            Ok(())
+        }
+
+        let dir = tempfile::tempdir()?;
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test_workspaces");
+        for name in names {
+            copy_dir(&fixtures.join(name), &dir.path().join(name))?;
+        }
+        Ok(dir)
+    }
+
+    pub(crate) fn get_demo_meta(ix: usize) -> anyhow::Result<Metadata> {
         let path = format!(
             "{}/test_workspaces/{ix}/metadata.json",
             env!("CARGO_MANIFEST_DIR")
@@ -802,14 +1453,23 @@ mod test {
         Ok(cargo_metadata::MetadataCommand::parse(data)?)
     }
 
+    /// Resolver of a demo workspace, read from the fixture manifest rather than from the
+    /// workspace root recorded in its metadata
+    pub(crate) fn get_demo_resolver(ix: usize) -> anyhow::Result<Resolver> {
+        let path = format!(
+            "{}/test_workspaces/{ix}/Cargo.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        Resolver::from_manifest(&std::fs::read_to_string(path)?, None)
+    }
+
     fn process_fg_with<F>(ix: usize, op: F) -> anyhow::Result<()>
     where
         F: FnOnce(&mut FeatGraph) -> anyhow::Result<()>,
     {
         let meta = get_demo_meta(ix)?;
         let platform = target_spec::Platform::current()?;
-        let triplets = vec![platform.triple_str()];
-        let mut fg = FeatGraph::init(&meta, triplets, Vec::new())?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from(platform.triple_str())])?;
         op(&mut fg)
     }
 
@@ -831,6 +1491,102 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn self_dev_dependency_is_a_cycle() -> anyhow::Result<()> {
+        process_fg_with(4, |fg| {
+            let cycles = fg.cycles();
+            assert!(!cycles.is_empty());
+            for cycle in &cycles {
+                assert!(cycle
+                    .iter()
+                    .all(|&ix| fg.features[ix].pid().unwrap().package().name == "beta"));
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn cycles_dont_prevent_reduction() -> anyhow::Result<()> {
+        process_fg_with(4, |fg| {
+            let cycles = fg.cycles().len();
+            fg.optimize(false)?;
+            assert!(!fg.reduced_edges.is_empty());
+            // links inside of the cycle are kept
+            assert_eq!(fg.cycles().len(), cycles);
+            Ok(())
+        })
+    }
+
+    fn find(fg: &FeatGraph, name: &str, feat: Option<&str>) -> NodeIndex {
+        fg.features
+            .node_indices()
+            .find(|&ix| {
+                fg.features[ix].fid().is_some_and(|fid| {
+                    fid.pid.package().name == name
+                        && match (fid.dep, feat) {
+                            (Feat::Base, None) => true,
+                            (Feat::Named(n), Some(f)) => n == f,
+                            _ => false,
+                        }
+                })
+            })
+            .expect("node must exist")
+    }
+
+    #[test]
+    fn reachability_follows_kinds_and_targets() -> anyhow::Result<()> {
+        let meta = get_demo_meta(4)?;
+        let target = Target {
+            triple: "x86_64-unknown-linux-gnu".to_string(),
+            cfgs: vec!["target_family=\"unix\"".parse()?],
+        };
+        let fg = FeatGraph::init(&meta, vec![target])?;
+        let alpha = find(&fg, "alpha", None);
+        let unix = find(&fg, "beta", Some("unix"));
+        let windows = find(&fg, "beta", Some("windows"));
+        assert!(fg.reachable(alpha, unix, Collect::Target));
+        assert!(!fg.reachable(alpha, windows, Collect::Target));
+        assert!(fg.reachable(alpha, windows, Collect::AllTargets));
+
+        let meta = get_demo_meta(12)?;
+        let fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let alpha = find(&fg, "alpha", None);
+        let delta = find(&fg, "delta", None);
+        let member = fg.features[alpha].pid().unwrap();
+        assert!(!fg.reachable(alpha, delta, Collect::MemberNorm(member)));
+        assert!(fg.reachable(alpha, delta, Collect::MemberBuild(member)));
+        Ok(())
+    }
+
+    #[test]
+    fn text_export_tells_crates_from_features() -> anyhow::Result<()> {
+        process_fg_with(2, |fg| {
+            let text = fg.to_text();
+            assert!(text.lines().any(|l| l == "crate alpha 0.1.0 (member)"));
+            assert!(text
+                .lines()
+                .any(|l| l == "feature beta 0.1.0:debug (member)"));
+            assert!(text
+                .lines()
+                .any(|l| l.starts_with("    -> crate beta 0.1.0")));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn json_export() -> anyhow::Result<()> {
+        process_fg_with(2, |fg| {
+            let json = fg.to_json();
+            let nodes = json["nodes"].as_array().unwrap();
+            assert!(nodes
+                .iter()
+                .any(|n| n["name"] == "beta" && n["feature"] == "debug" && n["workspace"] == true));
+            let edges = json["edges"].as_array().unwrap();
+            assert!(edges.iter().all(|e| e["kinds"].as_array().is_some()));
+            Ok(())
+        })
+    }
+
     #[test]
     fn metadata_snapshot_5() -> anyhow::Result<()> {
         process_fg_with(5, |_fg| {
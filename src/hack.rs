@@ -1,79 +1,412 @@
 #![allow(clippy::similar_names)]
 
 use crate::{
+    explain::selected_features,
     feat_graph::{Feat, FeatGraph, Pid},
-    metadata::DepKindInfo,
-    source::ChangePackage,
-    toml::set_dependencies,
+    metadata::{DepKindInfo, DependencyKind, Resolver, Target},
+    opts::{FeatureSelection, RenameStrategy},
+    source::{ChangePackage, Registries},
+    toml::{is_hacked, set_dependencies, set_workspace_dependencies, Banner},
+};
+use anyhow::Context;
+use cargo_metadata::{
+    camino::{Utf8Path, Utf8PathBuf},
+    Metadata, Package,
 };
-use cargo_metadata::Metadata;
-use cargo_platform::Cfg;
 use petgraph::{
     graph::NodeIndex,
     visit::{Dfs, DfsPostOrder, EdgeFiltered, EdgeRef, NodeFiltered, VisitMap, Walker},
 };
-use std::collections::{BTreeMap, BTreeSet};
+use rayon::prelude::*;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 use tracing::{debug, info, trace, warn};
 
+static PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Report on stderr how many members are checked while calculating the changeset
+pub fn show_progress(enabled: bool) {
+    PROGRESS.store(enabled, Ordering::Relaxed);
+}
+
+fn report_progress(done: usize, total: usize) {
+    info!("Checking member {done} of {total}");
+    if PROGRESS.load(Ordering::Relaxed) {
+        eprint!("\rChecking member {done} of {total}");
+        if done == total {
+            eprintln!();
+        }
+    }
+}
+
 fn force_config(var: &mut bool, name: &str, meta: &serde_json::Value) -> Option<()> {
     *var = meta.get("hackerman")?.get(name)?.as_bool()?;
     Some(())
 }
 
-pub fn hack(
-    dry: bool,
-    mut lock: bool,
-    mut no_dev: bool,
-    meta: &Metadata,
-    triplets: Vec<&str>,
-    cfgs: Vec<Cfg>,
-) -> anyhow::Result<bool> {
-    force_config(&mut lock, "lock", &meta.workspace_metadata);
-    force_config(&mut no_dev, "no-dev", &meta.workspace_metadata);
+/// Unification settings for a single member
+///
+/// Starts from the command line flags overridden by `[workspace.metadata.hackerman]`, keys
+/// from `[package.metadata.hackerman]` of the member override both
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemberConfig {
+    pub no_dev: bool,
+    pub no_default: bool,
+    pub skip_proc_macro: bool,
+    pub direct_only: bool,
+}
 
-    let mut fg = FeatGraph::init(meta, triplets, cfgs)?;
-    let changeset = get_changeset(&mut fg, no_dev)?;
-    let has_changes = !changeset.is_empty();
+impl MemberConfig {
+    fn of(mut self, member: Pid) -> Self {
+        let meta = &member.package().metadata;
+        force_config(&mut self.no_dev, "no-dev", meta);
+        force_config(&mut self.no_default, "no-default", meta);
+        force_config(&mut self.skip_proc_macro, "skip-proc-macro", meta);
+        force_config(&mut self.direct_only, "direct-only", meta);
+        self
+    }
+}
 
-    if dry {
-        if changeset.is_empty() {
-            println!("Features are unified as is");
-            return Ok(false);
+/// Flags of `hack`, see [`Action::Hack`](crate::opts::Action::Hack) for what they do
+///
+/// Most of them can be set in `[workspace.metadata.hackerman]` as well, see
+/// [`HackOptions::with_config`]
+#[derive(Debug, Clone, Default)]
+pub struct HackOptions {
+    pub lock: bool,
+    pub no_dev: bool,
+    pub no_default: bool,
+    pub rename: RenameStrategy,
+    pub skip_proc_macro: bool,
+    pub direct_only: bool,
+    pub workspace_deps: bool,
+    /// hack the rest of the members when some of them fail
+    pub keep_going: bool,
+    /// write applied changes as json
+    pub report: Option<PathBuf>,
+    /// write changes as a unified diff instead of changing the manifests
+    pub patch: Option<PathBuf>,
+    pub no_banner: bool,
+    pub selection: FeatureSelection,
+}
+
+impl HackOptions {
+    /// Apply settings from `[workspace.metadata.hackerman]`, they override the flags
+    /// Unification settings shared by all the members, before per member overrides
+    #[must_use]
+    pub const fn member_config(&self) -> MemberConfig {
+        MemberConfig {
+            no_dev: self.no_dev,
+            no_default: self.no_default,
+            skip_proc_macro: self.skip_proc_macro,
+            direct_only: self.direct_only,
         }
-        println!("Hackerman would like to set those features for following packets:");
     }
 
-    for (member, changes) in changeset {
-        let mut changeset = changes
-            .into_iter()
-            .map(|change| ChangePackage::make(member, change))
-            .collect::<anyhow::Result<Vec<_>>>()?;
-
-        if dry {
-            changeset.sort_by(|a, b| a.name.cmp(&b.name));
-            let path = &member.package().manifest_path;
-            println!("{path}");
-            for change in changeset {
-                let t = match change.ty {
-                    Ty::Dev => "dev ",
-                    Ty::Norm => "",
-                };
-                println!(
-                    "\t{} {} {}: {t}{:?}",
-                    change.name, change.version, change.source, change.feats
-                );
-            }
+    pub fn with_config(mut self, meta: &Metadata) -> anyhow::Result<Self> {
+        let config = &meta.workspace_metadata;
+        force_config(&mut self.lock, "lock", config);
+        force_config(&mut self.workspace_deps, "workspace-deps", config);
+        force_config(&mut self.no_dev, "no-dev", config);
+        force_config(&mut self.no_default, "no-default", config);
+        force_config(&mut self.skip_proc_macro, "skip-proc-macro", config);
+        force_config(&mut self.direct_only, "direct-only", config);
+        if let Some(strategy) = config
+            .get("hackerman")
+            .and_then(|h| h.get("rename"))
+            .and_then(serde_json::Value::as_str)
+        {
+            self.rename = strategy.parse().map_err(anyhow::Error::msg)?;
+        }
+        Ok(self)
+    }
+}
+
+/// Members that need unification along with changes to their manifests
+pub type Changeset<'a> = Vec<(Pid<'a>, Vec<ChangePackage<'a>>)>;
+
+/// Changes `hack` would make to every member that needs them, nothing is written
+///
+/// Members and their changes come in no particular order, see [`sort_changes`]
+pub fn hack_changes<'a>(
+    opts: &HackOptions,
+    meta: &'a Metadata,
+    targets: Vec<Target>,
+) -> anyhow::Result<(Changeset<'a>, Compilations)> {
+    changes_with(&opts.clone().with_config(meta)?, meta, targets)
+}
+
+/// Same as [`hack_changes`] with workspace config already applied to `opts`
+fn changes_with<'a>(
+    opts: &HackOptions,
+    meta: &'a Metadata,
+    targets: Vec<Target>,
+) -> anyhow::Result<(Changeset<'a>, Compilations)> {
+    let registries = Registries::load(&meta.workspace_root);
+
+    let resolver = Resolver::of_workspace(meta)?;
+    if resolver == Resolver::V2 {
+        let inactive = inactive_target_deps(meta, &targets);
+        if !inactive.is_empty() {
+            let triples = targets
+                .iter()
+                .map(|t| t.triple.as_str())
+                .collect::<Vec<_>>();
+            warn!(
+                "Workspace uses resolver 2 which doesn't unify features of dependencies for \
+                inactive targets, unification is only exact for {triples:?}. Use --target to \
+                add more targets. Inactive dependencies: {}",
+                inactive.join(", ")
+            );
+        }
+    }
+
+    let mut fg = FeatGraph::init(meta, targets)?;
+    select_features(&mut fg, &opts.selection)?;
+    let (changeset, compilations) = get_changeset(&mut fg, resolver, &opts.member_config())?;
+
+    let changeset = changeset
+        .into_iter()
+        .map(|(member, changes)| {
+            let changes = changes
+                .into_iter()
+                .map(|change| ChangePackage::make(member, change, &registries))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok((member, changes))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // checked before anything is written so a refused hack leaves no manifests behind
+    let conflicts = changeset
+        .iter()
+        .flat_map(|(member, changes)| optional_conflicts(member.package(), changes))
+        .collect::<Vec<_>>();
+    if !conflicts.is_empty() {
+        anyhow::bail!(
+            "Unification would make optional dependencies required, cargo rejects manifests \
+            that refer to required dependencies as optional ones: {}",
+            conflicts.join(", ")
+        );
+    }
+    Ok((changeset, compilations))
+}
+
+/// Unify features of workspace members, returns `true` if any manifests were changed
+pub fn hack(opts: &HackOptions, meta: &Metadata, targets: Vec<Target>) -> anyhow::Result<bool> {
+    if opts.report.is_some() && opts.patch.is_some() {
+        anyhow::bail!(
+            "Report describes applied changes and can't be written together with a patch"
+        );
+    }
+    let opts = opts.clone().with_config(meta)?;
+    let members = meta.workspace_members.iter().collect::<BTreeSet<_>>();
+    let mut hacked = Vec::new();
+    for package in &meta.packages {
+        if members.contains(&package.id) && is_hacked(&package.manifest_path)? {
+            hacked.push(package.manifest_path.as_str());
+        }
+    }
+    let root = meta.workspace_root.join("Cargo.toml");
+    if !hacked.iter().any(|&m| root == m) && is_hacked(&root)? {
+        hacked.push(root.as_str());
+    }
+    if !hacked.is_empty() {
+        anyhow::bail!(
+            "Workspace is already hacked, run `cargo hackerman restore` first. \
+            Hacked manifests: {}",
+            hacked.join(", ")
+        );
+    }
+
+    let (mut changeset, _) = changes_with(&opts, meta, targets)?;
+    let banner = if opts.no_banner {
+        Banner::Disabled
+    } else {
+        Banner::from_metadata(&meta.workspace_metadata)?
+    };
+
+    // manifests are only written once all of them are hacked in memory, a failure
+    // leaves the workspace as it was. Paths map to original and hacked contents
+    let mut staged = BTreeMap::new();
+    if opts.workspace_deps {
+        let mut members = changeset
+            .iter_mut()
+            .map(|(member, changes)| (member.package().manifest_path.as_path(), changes))
+            .collect::<Vec<_>>();
+        // members only get the banner along with the lock, a root that is also a changed
+        // member gets it with its own changes
+        let root_banner = if !opts.lock || members.iter().any(|(path, _)| *path == root) {
+            Banner::Disabled
         } else {
-            let path = &member.package().manifest_path;
-            set_dependencies(path, lock, &changeset)?;
+            banner.clone()
+        };
+        let original =
+            std::fs::read_to_string(&root).with_context(|| format!("Failed to read {root}"))?;
+        if let Some(hacked) =
+            set_workspace_dependencies(&root, &original, &root_banner, &mut members)?
+        {
+            staged.insert(root, (original, hacked));
+        }
+    }
+
+    // members are separate files, they can be hacked independently
+    let results = changeset.par_iter().map(|(member, changeset)| {
+        let path = &member.package().manifest_path;
+        let (original, current) = match staged.get(path) {
+            Some((original, hacked)) => (original.clone(), hacked.clone()),
+            None => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to update {path}"))?;
+                (text.clone(), text)
+            }
+        };
+        let hacked = set_dependencies(&current, opts.lock, opts.rename, &banner, changeset)
+            .with_context(|| format!("Failed to update {path}"))?;
+        let manifest = serde_json::json!({
+            "manifest": path,
+            "changes": changeset.iter().map(ChangePackage::to_json).collect::<Vec<_>>(),
+        });
+        Ok((path.clone(), original, hacked, manifest))
+    });
+    let mut failed = Vec::new();
+    let ready = if opts.keep_going {
+        let mut ready = Vec::new();
+        for res in results.collect::<Vec<anyhow::Result<_>>>() {
+            match res {
+                Ok(member) => ready.push(member),
+                Err(err) => failed.push(format!("{err:#}")),
+            }
+        }
+        ready
+    } else {
+        results.collect::<anyhow::Result<Vec<_>>>()?
+    };
+    let mut applied = Vec::new();
+    for (path, original, hacked, manifest) in ready {
+        staged.insert(path, (original, hacked));
+        applied.push(manifest);
+    }
+    match &opts.patch {
+        Some(patch) => write_patch(&meta.workspace_root, &staged, patch)?,
+        None => write_manifests(&staged)?,
+    }
+
+    if let Some(report) = &opts.report {
+        let report_json = serde_json::json!({ "manifests": applied });
+        std::fs::write(report, serde_json::to_string_pretty(&report_json)? + "\n")
+            .with_context(|| format!("Failed to write report to {}", report.display()))?;
+    }
+
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "Failed to update {} of {} manifests, the rest are hacked. \
+            Run `cargo hackerman restore` to undo the changes:\n{}",
+            failed.len(),
+            failed.len() + applied.len(),
+            failed.join("\n")
+        );
+    }
+
+    Ok(!changeset.is_empty())
+}
+
+/// Write changes to all the hacked manifests as a single patch instead of changing them
+///
+/// Paths in the patch are relative to the workspace root
+fn write_patch(
+    root: &Utf8Path,
+    staged: &BTreeMap<Utf8PathBuf, (String, String)>,
+    patch: &Path,
+) -> anyhow::Result<()> {
+    let mut diff = String::new();
+    for (path, (original, hacked)) in staged {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        diff += &crate::patch::unified_diff(relative.as_str(), original, hacked);
+    }
+    std::fs::write(patch, diff)
+        .with_context(|| format!("Failed to write patch to {}", patch.display()))
+}
+
+/// Write hacked manifests, manifests already written are rolled back if any of the writes fail
+fn write_manifests(staged: &BTreeMap<Utf8PathBuf, (String, String)>) -> anyhow::Result<()> {
+    let results = staged
+        .par_iter()
+        .map(|(path, (_, hacked))| {
+            info!("updating {path}");
+            std::fs::write(path, hacked).with_context(|| format!("Failed to update {path}"))
+        })
+        .collect::<Vec<_>>();
+    if results.iter().all(Result::is_ok) {
+        return Ok(());
+    }
+    let mut errors = Vec::new();
+    for ((path, (original, _)), res) in staged.iter().zip(results) {
+        match res {
+            Ok(()) => {
+                if let Err(err) = std::fs::write(path, original) {
+                    warn!("Failed to roll back {path}: {err}");
+                }
+            }
+            Err(err) => errors.push(format!("{err:#}")),
         }
     }
+    anyhow::bail!(
+        "{}, the rest of the manifests are left as they were",
+        errors.join(", ")
+    )
+}
 
-    if dry && has_changes {
-        anyhow::bail!("Features are not unified");
+/// Manifests of workspace members that need unification along with the dependencies they
+/// need changed, settings from workspace metadata are applied the same way `hack` applies them
+pub fn members_to_hack<'a>(
+    no_dev: bool,
+    no_default: bool,
+    selection: &FeatureSelection,
+    meta: &'a Metadata,
+    resolver: Resolver,
+    targets: Vec<Target>,
+) -> anyhow::Result<BTreeMap<&'a Utf8Path, Vec<FeatChange<'a>>>> {
+    let opts = HackOptions {
+        no_dev,
+        no_default,
+        ..HackOptions::default()
     }
+    .with_config(meta)?;
+    let mut fg = FeatGraph::init(meta, targets)?;
+    select_features(&mut fg, selection)?;
+    let (changeset, _) = get_changeset(&mut fg, resolver, &opts.member_config())?;
+    Ok(changeset
+        .into_iter()
+        .map(|(member, changes)| (member.package().manifest_path.as_path(), changes))
+        .collect())
+}
 
-    Ok(has_changes)
+/// Features requested by members that their dependencies no longer have, taken from the
+/// `cargo metadata` error
+///
+/// Lock file updates can pick a version of a dependency that dropped a feature hackerman
+/// unified earlier, cargo refuses to resolve such workspace and names the first one it finds
+pub fn stale_features(cargo_error: &str) -> Vec<String> {
+    cargo_error
+        .lines()
+        .filter_map(|line| {
+            // package `alpha` depends on `delta` with feature `two` but `delta` does not have
+            // that feature.
+            let rest = line.trim().strip_prefix("package `")?;
+            let (member, rest) = rest.split_once("` depends on `")?;
+            let (dep, rest) = rest.split_once("` with feature `")?;
+            let (feature, rest) = rest.split_once("` but `")?;
+            rest.ends_with("` does not have that feature.").then(|| {
+                format!(
+                    "in {member}: feature {feature} of {dep} no longer exists; restore and re-hack"
+                )
+            })
+        })
+        .collect()
 }
 
 pub struct FeatChange<'a> {
@@ -88,29 +421,57 @@ pub struct FeatChange<'a> {
 
     /// Features to add
     pub features: BTreeSet<String>,
+
+    /// Features the member doesn't enable on its own, subset of `features`
+    pub missing: BTreeSet<String>,
+
+    /// Source members declare the dependency with, if `[patch]` replaces it with a package
+    /// from somewhere else
+    pub declared: Option<&'a str>,
 }
 
 type FeatChanges<'a> = BTreeMap<Pid<'a>, Vec<FeatChange<'a>>>;
-type DetachedDepTree = BTreeMap<NodeIndex, BTreeSet<NodeIndex>>;
 
-fn show_detached_dep_tree(tree: &DetachedDepTree, fg: &FeatGraph) -> &'static str {
+/// How many separate compilations of dependencies unification saves
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Compilations {
+    /// Dependencies used with more than one feature set
+    pub deps: usize,
+    /// Distinct feature sets of those dependencies, before unification
+    pub before: usize,
+}
+
+impl std::fmt::Display for Compilations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unifying these {} dependencies collapses {} distinct feature sets into {}",
+            self.deps, self.before, self.deps
+        )
+    }
+}
+pub(crate) type DetachedDepTree = BTreeMap<NodeIndex, BTreeSet<NodeIndex>>;
+/// Links from workspace members to features they need to add, both are feature graph indices
+pub(crate) type ExtraEdges = BTreeMap<NodeIndex, BTreeSet<NodeIndex>>;
+
+fn show_detached_dep_tree(tree: &DetachedDepTree, fg: &FeatGraph) -> String {
+    use std::fmt::Write;
     let mut t = tree.iter().collect::<Vec<_>>();
 
     t.sort_by(|a, b| fg.features[*a.0].fid().cmp(&fg.features[*b.0].fid()));
 
+    let mut res = String::new();
     for (&package, feats) in t {
-        //tree.iter() {
         let package = fg.features[package];
-        print!("{package}\n\t");
+        let _ = write!(res, "\n{package}\n\t");
         for feature in feats.iter().copied() {
             let feature = fg.features[feature];
             let fid = feature.fid().unwrap();
             assert_eq!(package.fid().unwrap().pid, fid.pid);
-            print!("{} ", fid.dep);
+            let _ = write!(res, "{} ", fid.dep);
         }
-        println!();
     }
-    ""
+    res
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -119,44 +480,47 @@ pub enum Collect<'a> {
     AllTargets,
     /// all targets, normal dependencies only
     NormalOnly,
+    /// all targets, normal dependencies and dev dependencies of workspace members
+    NormalAndMemberDev,
     /// current target only
     Target,
     /// current target only, normal and build dependencies globally, dev dependencies for workspace
     DevTarget,
     NoDev,
     MemberDev(Pid<'a>),
+    /// current target only, starting from a workspace member, no dev and no build dependencies
+    /// of that member
+    MemberNorm(Pid<'a>),
+    /// current target only, starting from a workspace member, build dependencies of that member
+    MemberBuild(Pid<'a>),
 }
 
-// we are doing 4 types of passes:
+// we are doing 5 types of passes:
 // 1. everything for all the targets
 // 2. everything for this target - this is used to filter the first one
-// 3. starting from a workspace member, no dev
-// 4. starting from a workspace member, dev for that membe only
+// 3. starting from a workspace member, no dev and no build
+// 4. starting from a workspace member, build for that member only
+// 5. starting from a workspace member, dev for that membe only
 
-fn collect_features_from<M>(
+pub(crate) fn collect_features_from<M>(
     dfs: &mut Dfs<NodeIndex, M>,
     fg: &FeatGraph,
     to: &mut DetachedDepTree,
     filter: Collect,
+    extra: &ExtraEdges,
 ) where
     M: VisitMap<NodeIndex>,
 {
     let mut to_visit = Vec::new();
     let mut added = BTreeSet::new();
 
-    let g = EdgeFiltered::from_fn(&fg.features, |e| {
-        // last_edge.set(Some(e));
-        match filter {
-            Collect::AllTargets => true,
-            Collect::Target | Collect::NoDev | Collect::DevTarget | Collect::MemberDev(_) => e
-                .weight()
-                .satisfies(fg.features[e.source()], filter, &fg.platforms, &fg.cfgs),
-            Collect::NormalOnly => e.weight().is_normal(),
-        }
-    });
+    let g = EdgeFiltered::from_fn(&fg.features, |e| fg.follows(e, filter));
 
     loop {
         while let Some(ix) = dfs.next(&g) {
+            if let Some(links) = extra.get(&ix) {
+                dfs.stack.extend(links.iter().copied());
+            }
             if let Some(fid) = fg.features[ix].fid() {
                 if let Some(parent) = fg.fid_cache.get(&fid.get_base()) {
                     to.entry(*parent).or_default().insert(ix);
@@ -164,10 +528,16 @@ fn collect_features_from<M>(
             }
         }
         for t in fg.triggers.iter() {
-            let package = fg.fid_cache[&t.package.base().get_base()];
-            let feature = fg.fid_cache[&t.feature]; // .unwrap();
-            let weak_dep = fg.fid_cache[&t.weak_dep];
-            let weak_feat = fg.fid_cache[&t.weak_feat];
+            // some of the nodes might be gone after the graph was shrunk to the target,
+            // such triggers can't fire
+            let (Some(&package), Some(&feature), Some(&weak_dep), Some(&weak_feat)) = (
+                fg.fid_cache.get(&t.package.base()),
+                fg.fid_cache.get(&t.feature),
+                fg.fid_cache.get(&t.weak_dep),
+                fg.fid_cache.get(&t.weak_feat),
+            ) else {
+                continue;
+            };
 
             if let Some(dep) = to.get(&package) {
                 if dep.contains(&feature) && dep.contains(&weak_dep) && added.insert(weak_feat) {
@@ -188,6 +558,7 @@ fn collect_features_from<M>(
 pub enum Ty {
     Dev,
     Norm,
+    Build,
 }
 
 impl Ty {
@@ -196,6 +567,7 @@ impl Ty {
         match self {
             Ty::Dev => "dev-dependencies",
             Ty::Norm => "dependencies",
+            Ty::Build => "build-dependencies",
         }
     }
 }
@@ -205,12 +577,381 @@ impl std::fmt::Display for Ty {
         match self {
             Ty::Dev => f.write_str("dev"),
             Ty::Norm => f.write_str("norm"),
+            Ty::Build => f.write_str("build"),
+        }
+    }
+}
+
+/// Split members into waves such that each member only depends on members from previous waves
+///
+/// `members` must be in postorder, only members that come earlier are considered as
+/// dependencies, same as with sequential processing
+fn member_waves<'a>(
+    fg: &FeatGraph<'a>,
+    members: &[(Pid<'a>, NodeIndex)],
+) -> Vec<Vec<(Pid<'a>, NodeIndex)>> {
+    let workspace_only_graph =
+        NodeFiltered::from_fn(&fg.features, |node| fg.features[node].is_workspace());
+    let mut wave_of = BTreeMap::new();
+    let mut waves: Vec<Vec<_>> = Vec::new();
+    for &(member, member_ix) in members {
+        let wave = Dfs::new(&workspace_only_graph, member_ix)
+            .iter(&workspace_only_graph)
+            .filter_map(|ix| wave_of.get(&fg.features[ix].pid()?))
+            .map(|w| w + 1)
+            .max()
+            .unwrap_or(0);
+        wave_of.insert(member, wave);
+        if wave == waves.len() {
+            waves.push(Vec::new());
+        }
+        waves[wave].push((member, member_ix));
+    }
+    waves
+}
+
+/// Changes required by a single workspace member
+struct MemberChanges {
+    /// workspace features of dependencies the member lacks
+    changes: BTreeMap<(Ty, NodeIndex), BTreeSet<NodeIndex>>,
+    /// part of `changes` the member doesn't get without them
+    missing: BTreeMap<(Ty, NodeIndex), BTreeSet<NodeIndex>>,
+    /// new normal links from the member
+    norm: BTreeSet<NodeIndex>,
+    /// new dev links from the member
+    dev: BTreeSet<NodeIndex>,
+    /// new build links from the member
+    build: BTreeSet<NodeIndex>,
+    /// feature sets of workspace dependencies as requested by the member before any changes
+    requested: Vec<(NodeIndex, BTreeSet<NodeIndex>)>,
+}
+
+impl MemberChanges {
+    fn request(
+        &mut self,
+        feats: &DetachedDepTree,
+        ws_feats: &DetachedDepTree,
+        skipped: impl Fn(NodeIndex) -> bool,
+    ) {
+        self.requested.extend(
+            feats
+                .iter()
+                .filter(|(&dep, _)| ws_feats.contains_key(&dep) && !skipped(dep))
+                .map(|(&dep, feats)| (dep, feats.clone())),
+        );
+    }
+}
+
+/// Read only view shared by all the members during changeset calculation
+struct MemberEnv<'g, 'a> {
+    fg: &'g FeatGraph<'a>,
+    raw_workspace_feats: &'g DetachedDepTree,
+    /// same as `raw_workspace_feats` but following build dependencies as well
+    build_workspace_feats: &'g DetachedDepTree,
+    filtered_workspace_feats: &'g DetachedDepTree,
+    /// normal links added to members processed earlier
+    extra: &'g ExtraEdges,
+    no_dev: bool,
+    /// don't add `default` feature of a dependency to members that don't use it
+    no_default: bool,
+    /// only unify dependencies members declare themselves
+    direct_only: bool,
+    /// leave features of proc-macro crates as is
+    skip_proc_macro: bool,
+}
+
+impl MemberEnv<'_, '_> {
+    /// Proc-macro dependency left as is with `skip_proc_macro`
+    fn skipped(&self, dep: NodeIndex) -> bool {
+        self.skip_proc_macro
+            && self.fg.features[dep]
+                .pid()
+                .is_some_and(|pid| is_proc_macro(pid.package()))
+    }
+
+    /// Workspace features of `dep` the member should have given features it already has
+    ///
+    /// With `no_default` a member that doesn't use `default` of a dependency doesn't get it
+    /// along with anything enabled only by `default`.
+    fn wanted(
+        &self,
+        ws: &DetachedDepTree,
+        dep: NodeIndex,
+        feats: &BTreeSet<NodeIndex>,
+    ) -> Option<BTreeSet<NodeIndex>> {
+        let ws_feats = ws.get(&dep)?;
+        if !self.no_default {
+            return Some(ws_feats.clone());
+        }
+        let fg = self.fg;
+        let pid = fg.features[dep].pid()?;
+        let Some(&default) = fg.fid_cache.get(&pid.named("default")) else {
+            return Some(ws_feats.clone());
+        };
+        if feats.contains(&default) {
+            return Some(ws_feats.clone());
+        }
+
+        // named features of the same crate reachable from a feature, the feature included
+        let closure = |start: NodeIndex| {
+            let same_crate =
+                NodeFiltered::from_fn(&fg.features, |ix| fg.features[ix].pid() == Some(pid));
+            Dfs::new(&same_crate, start)
+                .iter(&same_crate)
+                .collect::<BTreeSet<_>>()
+        };
+        let by_default = closure(default);
+        let mut res = feats.clone();
+        for &feat in ws_feats {
+            if !by_default.contains(&feat) {
+                res.extend(closure(feat));
+            }
+        }
+        res.retain(|f| ws_feats.contains(f) || feats.contains(f));
+        Some(res)
+    }
+
+    /// Keep adding features the member lacks until there's nothing to add, return new links
+    ///
+    /// `seed` contains links added by earlier passes, they are not in the graph yet but the
+    /// traversal should follow them
+    fn unify(
+        &self,
+        res: &mut MemberChanges,
+        (member, member_ix): (Pid, NodeIndex),
+        (ty, filter): (Ty, Collect),
+        ws: &DetachedDepTree,
+        seed: &BTreeSet<NodeIndex>,
+    ) -> BTreeSet<NodeIndex> {
+        let fg = self.fg;
+        let direct = self.direct_only.then(|| direct_dependencies(fg, member));
+        let mut added = BTreeSet::new();
+        let mut dfs = Dfs::new(&fg.features, member_ix);
+        dfs.stack.extend(seed.iter().copied());
+        let mut deps_feats = BTreeMap::new();
+        'dependency: loop {
+            // DFS traverse of the current member and everything below it
+            collect_features_from(&mut dfs, fg, &mut deps_feats, filter, self.extra);
+            deps_feats.retain(|key, _val| self.filtered_workspace_feats.contains_key(key));
+            if added.is_empty() {
+                res.request(&deps_feats, ws, |dep| self.skipped(dep));
+            }
+
+            debug!(
+                "Accumulated {ty} deps for {:?} are as following:{}",
+                member.package().name,
+                show_detached_dep_tree(&deps_feats, fg),
+            );
+
+            for (&dep, feats) in &deps_feats {
+                if let Some(direct) = &direct {
+                    if !fg.features[dep]
+                        .pid()
+                        .is_some_and(|pid| direct.contains(&pid))
+                    {
+                        continue;
+                    }
+                }
+                // skipped features must not reach dependent members through added links
+                if self.skipped(dep) {
+                    debug!("Skipping proc-macro {} for {member:?}", fg.features[dep]);
+                    continue;
+                }
+                if let Some(ws_feats) = self.wanted(ws, dep, feats) {
+                    if &ws_feats != feats {
+                        if let Some(&missing_feat) = ws_feats.difference(feats).next() {
+                            info!("\t{member:?} lacks {ty} {}", fg.features[missing_feat]);
+
+                            res.changes.insert((ty, dep), ws_feats.clone());
+                            res.missing
+                                .entry((ty, dep))
+                                .or_insert_with(|| ws_feats.difference(feats).copied().collect());
+                            added.insert(missing_feat);
+                            dfs.move_to(missing_feat);
+
+                            trace!("Performing one more {ty} iteration on {member:?}");
+                            continue 'dependency;
+                        }
+                    }
+                }
+            }
+
+            break;
+        }
+        added
+    }
+
+    fn changes(&self, member: Pid, member_ix: NodeIndex) -> MemberChanges {
+        let mut res = MemberChanges {
+            changes: BTreeMap::new(),
+            missing: BTreeMap::new(),
+            norm: BTreeSet::new(),
+            dev: BTreeSet::new(),
+            build: BTreeSet::new(),
+            requested: Vec::new(),
+        };
+        info!("==== Checking {member:?}");
+
+        // Nothing to unify for a crate that depends on nothing. Dependencies on other members
+        // still need a full check: features of their dependencies can differ here.
+        if member.package().dependencies.is_empty() {
+            debug!("No dependencies for {member:?}, skipping");
+            return res;
+        }
+        let this = (member, member_ix);
+        let has_deps = |kind| member.package().dependencies.iter().any(|d| d.kind == kind);
+
+        // For every workspace member we start collecting features it uses, similar to
+        // workspace_feats above
+        let norm = (Ty::Norm, Collect::MemberNorm(member));
+        res.norm = self.unify(
+            &mut res,
+            this,
+            norm,
+            self.raw_workspace_feats,
+            &BTreeSet::new(),
+        );
+
+        // at this point dep_feats contains all the normal features used by {member}.
+        // build and dev passes start from it.
+        let seed = res.norm.clone();
+        if has_deps(cargo_metadata::DependencyKind::Build) {
+            let build = (Ty::Build, Collect::MemberBuild(member));
+            res.build = self.unify(&mut res, this, build, self.build_workspace_feats, &seed);
+        } else {
+            debug!("No build dependencies for {member:?}, skipping");
+        }
+
+        if self.no_dev {
+            return res;
+        }
+
+        if has_deps(cargo_metadata::DependencyKind::Development) {
+            let dev = (Ty::Dev, Collect::MemberDev(member));
+            res.dev = self.unify(&mut res, this, dev, self.raw_workspace_feats, &seed);
+        } else {
+            debug!("No dev dependencies for {member:?}, skipping");
+        }
+        res
+    }
+}
+
+/// Nodes build dependencies link to: targets of build links from anything the workspace
+/// builds on the current target following normal links only
+///
+/// Features reachable only through normal links are compiled for the target and are never
+/// unified with features of build dependencies
+fn build_entries(fg: &FeatGraph) -> Vec<NodeIndex> {
+    let normal = EdgeFiltered::from_fn(&fg.features, |e| {
+        e.weight().is_normal() && fg.follows(e, Collect::Target)
+    });
+    let mut res = Vec::new();
+    let mut dfs = Dfs::new(&normal, fg.root);
+    while let Some(ix) = dfs.next(&normal) {
+        for e in fg.features.edges(ix) {
+            if e.weight().has_kind(DependencyKind::Build) && fg.follows(e, Collect::Target) {
+                res.push(e.target());
+            }
+        }
+    }
+    res
+}
+
+/// Packages `member` links to directly
+fn direct_dependencies<'a>(fg: &FeatGraph<'a>, member: Pid<'a>) -> BTreeSet<Pid<'a>> {
+    fg.features
+        .edge_references()
+        .filter(|e| fg.features[e.source()].pid() == Some(member))
+        .filter_map(|e| fg.features[e.target()].pid())
+        .filter(|&pid| pid != member)
+        .collect()
+}
+
+fn is_proc_macro(package: &cargo_metadata::Package) -> bool {
+    package
+        .targets
+        .iter()
+        .any(|t| t.kind.iter().any(|k| k == "proc-macro"))
+}
+
+/// Make features from `selection` a part of what members enable by themselves, so both the
+/// workspace and every member built alone use them
+fn select_features(fg: &mut FeatGraph, selection: &FeatureSelection) -> anyhow::Result<()> {
+    for feature in selected_features(fg, selection)? {
+        let Some(fid) = fg.features[feature].fid() else {
+            continue;
+        };
+        if fid != fid.pid.root() {
+            fg.add_edge(fid.pid, feature, false, DepKindInfo::NORMAL)?;
+        }
+    }
+    Ok(())
+}
+
+/// Optional dependencies of `member` that `changes` replace with required ones while
+/// features of `member` still refer to them with `dep:name`, `name?/feature` or the implicit
+/// `name` feature
+fn optional_conflicts(member: &Package, changes: &[ChangePackage]) -> Vec<String> {
+    let mut res = Vec::new();
+    for change in changes.iter().filter(|c| !c.rename) {
+        let kind = match change.ty {
+            Ty::Norm => cargo_metadata::DependencyKind::Normal,
+            Ty::Build => cargo_metadata::DependencyKind::Build,
+            // dev dependencies can't be optional
+            Ty::Dev => continue,
+        };
+        let optional = member.dependencies.iter().any(|dep| {
+            dep.kind == kind
+                && dep.optional
+                && dep.rename.as_deref().unwrap_or(&dep.name) == change.name
+        });
+        if !optional {
+            continue;
+        }
+        let name = change.name.as_str();
+        for (feature, values) in &member.features {
+            for value in values {
+                let refers = value.strip_prefix("dep:") == Some(name)
+                    || value.split_once("?/").is_some_and(|(dep, _)| dep == name)
+                    || (value == name && !member.features.contains_key(name));
+                if refers {
+                    res.push(format!(
+                        "feature {feature} of {} refers to {name} as {value:?}",
+                        member.name
+                    ));
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Target specific dependencies of workspace members not used by any of the `targets`
+fn inactive_target_deps(meta: &Metadata, targets: &[Target]) -> Vec<String> {
+    let members = meta.workspace_members.iter().collect::<BTreeSet<_>>();
+    let mut res = Vec::new();
+    for package in &meta.packages {
+        if !members.contains(&package.id) {
+            continue;
+        }
+        for dep in &package.dependencies {
+            if let Some(platform) = &dep.target {
+                if !targets.iter().any(|t| t.matches(platform)) {
+                    res.push(format!("{} -> {} ({platform})", package.name, dep.name));
+                }
+            }
         }
     }
+    res
 }
 
-pub fn get_changeset<'a>(fg: &mut FeatGraph<'a>, no_dev: bool) -> anyhow::Result<FeatChanges<'a>> {
+pub fn get_changeset<'a>(
+    fg: &mut FeatGraph<'a>,
+    resolver: Resolver,
+    config: &MemberConfig,
+) -> anyhow::Result<(FeatChanges<'a>, Compilations)> {
     info!("==== Calculating changeset for hack");
+    let config = *config;
 
     //    dump(fg)?;
     let mut changed = BTreeMap::new();
@@ -221,12 +962,20 @@ pub fn get_changeset<'a>(fg: &mut FeatGraph<'a>, no_dev: bool) -> anyhow::Result
 
     // DetachedDepTree is used to avoid fighting the borrow checker.
     // indices correspond to features in graph
+    //
+    // Resolver 1 unifies features of dev dependencies of members with normal ones even
+    // when tests are not built, resolver 2 keeps them to the member's dev builds only.
+    let (global, target) = match resolver {
+        Resolver::V1 => (Collect::NormalAndMemberDev, Collect::DevTarget),
+        Resolver::V2 => (Collect::NormalOnly, Collect::Target),
+    };
     let mut raw_workspace_feats: DetachedDepTree = BTreeMap::new();
     collect_features_from(
         &mut Dfs::new(&fg.features, fg.root),
         fg,
         &mut raw_workspace_feats,
-        Collect::NormalOnly,
+        global,
+        &ExtraEdges::new(),
     );
 
     // For reasons unknown cargo resolves dependencies for all the targets including those
@@ -238,10 +987,24 @@ pub fn get_changeset<'a>(fg: &mut FeatGraph<'a>, no_dev: bool) -> anyhow::Result
         &mut Dfs::new(&fg.features, fg.root),
         fg,
         &mut filtered_workspace_feats,
-        Collect::Target,
+        target,
+        &ExtraEdges::new(),
     );
     raw_workspace_feats.retain(|k, _| filtered_workspace_feats.contains_key(k));
 
+    // build dependencies are unified separately and only for build dependencies of members
+    let mut build_workspace_feats = BTreeMap::new();
+    let mut build_dfs = Dfs::empty(&fg.features);
+    build_dfs.stack = build_entries(fg);
+    collect_features_from(
+        &mut build_dfs,
+        fg,
+        &mut build_workspace_feats,
+        Collect::Target,
+        &ExtraEdges::new(),
+    );
+    build_workspace_feats.retain(|k, _| filtered_workspace_feats.contains_key(k));
+
     info!(
         "Accumulated workspace dependencies{}",
         show_detached_dep_tree(&raw_workspace_feats, fg)
@@ -281,101 +1044,79 @@ pub fn get_changeset<'a>(fg: &mut FeatGraph<'a>, no_dev: bool) -> anyhow::Result
         res
     };
 
-    for (member, member_ix) in members.iter().copied() {
-        info!("==== Checking {member:?}");
-
-        // For every workspace member we start collecting features it uses, similar to
-        // workspace_feats above
-
-        let mut dfs = Dfs::new(&fg.features, member_ix);
-        let mut deps_feats = BTreeMap::new();
-        'dependency: loop {
-            collect_features_from(&mut dfs, fg, &mut deps_feats, Collect::NoDev);
-
-            debug!(
-                "Accumulated deps for {:?} are as following:{}",
-                member.package().name,
-                show_detached_dep_tree(&deps_feats, fg),
-            );
-
-            for (&dep, feats) in &deps_feats {
-                if let Some(ws_feats) = raw_workspace_feats.get(&dep) {
-                    if ws_feats != feats {
-                        if let Some(&missing_feat) = ws_feats.difference(feats).next() {
-                            info!("\t{member:?} lacks {}", fg.features[missing_feat]);
-
-                            changed
-                                .entry(member)
-                                .or_insert_with(BTreeMap::default)
-                                .insert((Ty::Norm, dep), ws_feats.clone());
-
-                            let new_dep =
-                                fg.add_edge(member_ix, missing_feat, false, DepKindInfo::NORMAL)?;
-                            dfs.move_to(new_dep);
-
-                            trace!("Performing one more iteration on {member:?}");
-                            continue 'dependency;
-                        }
-                    }
-                }
+    // Members are processed in postorder so features added to a member are visible to the
+    // members that depend on it. Members that don't depend on each other are independent and
+    // can be processed in parallel, one wave at a time.
+    let mut extra = ExtraEdges::new();
+    let mut dev_extra = ExtraEdges::new();
+    let mut build_extra = ExtraEdges::new();
+    let mut requested: BTreeMap<NodeIndex, BTreeSet<BTreeSet<NodeIndex>>> = BTreeMap::new();
+    let checked = AtomicUsize::new(0);
+    for wave in member_waves(fg, &members) {
+        let results = wave
+            .par_iter()
+            .map(|&(member, member_ix)| {
+                let done = checked.fetch_add(1, Ordering::Relaxed) + 1;
+                report_progress(done, members.len());
+                let config = config.of(member);
+                let env = MemberEnv {
+                    fg,
+                    raw_workspace_feats: &raw_workspace_feats,
+                    build_workspace_feats: &build_workspace_feats,
+                    filtered_workspace_feats: &filtered_workspace_feats,
+                    extra: &extra,
+                    no_dev: config.no_dev,
+                    no_default: config.no_default,
+                    direct_only: config.direct_only,
+                    skip_proc_macro: config.skip_proc_macro,
+                };
+                env.changes(member, member_ix)
+            })
+            .collect::<Vec<_>>();
+        for (&(member, member_ix), res) in wave.iter().zip(results) {
+            for (dep, feats) in res.requested {
+                requested.entry(dep).or_default().insert(feats);
+            }
+            if !res.changes.is_empty() {
+                changed.insert(member, (res.changes, res.missing));
+            }
+            if !res.norm.is_empty() {
+                extra.insert(member_ix, res.norm);
+            }
+            if !res.dev.is_empty() {
+                dev_extra.insert(member_ix, res.dev);
+            }
+            if !res.build.is_empty() {
+                build_extra.insert(member_ix, res.build);
             }
-
-            break;
-        }
-
-        if no_dev {
-            continue;
         }
+    }
 
-        // at this point dep_feats contains all the normal features used by {member}.
-        // we'll use it to filter dep dependencies if any.
-        if !member
-            .package()
-            .dependencies
-            .iter()
-            .any(|d| d.kind == cargo_metadata::DependencyKind::Development)
+    // building the whole workspace uses the unified feature set, members built on their own
+    // use whatever they request
+    let mut compilations = Compilations::default();
+    for (dep, mut sets) in requested {
+        if let Some(ws) = raw_workspace_feats
+            .get(&dep)
+            .or_else(|| build_workspace_feats.get(&dep))
         {
-            debug!("No dev dependencies for {member:?}, skipping");
-            continue;
+            sets.insert(ws.clone());
         }
+        if sets.len() > 1 {
+            compilations.deps += 1;
+            compilations.before += sets.len();
+        }
+    }
 
-        let mut dfs = Dfs::new(&fg.features, member_ix);
-        let mut dev_feats = BTreeMap::new();
-        'dev_dependency: loop {
-            // DFS traverse of the current member and everything below it
-            collect_features_from(&mut dfs, fg, &mut dev_feats, Collect::MemberDev(member));
-
-            dev_feats.retain(|key, _val| filtered_workspace_feats.contains_key(key));
-
-            debug!(
-                "Accumulated dev deps for {:?} are as following:{}",
-                member.package().name,
-                show_detached_dep_tree(&dev_feats, fg),
-            );
-
-            for (&dep, feats) in &dev_feats {
-                if let Some(ws_feats) = raw_workspace_feats.get(&dep) {
-                    if ws_feats != feats {
-                        if let Some(&missing_feat) = ws_feats.difference(feats).next() {
-                            debug!("\t{member:?} lacks dev {}", fg.features[missing_feat]);
-
-                            changed
-                                .entry(member)
-                                .or_insert_with(BTreeMap::default)
-                                .insert((Ty::Dev, dep), ws_feats.clone());
-
-                            let new_dep =
-                                fg.add_edge(member_ix, missing_feat, false, DepKindInfo::DEV)?;
-                            dfs.move_to(new_dep);
-
-                            trace!("Performing one more dev iteration on {member:?}");
-                            continue 'dev_dependency;
-                        }
-                    }
-                }
+    for (kind, links) in [
+        (DepKindInfo::NORMAL, extra),
+        (DepKindInfo::DEV, dev_extra),
+        (DepKindInfo::BUILD, build_extra),
+    ] {
+        for (member_ix, feats) in links {
+            for feat in feats {
+                fg.add_edge(member_ix, feat, false, kind.clone())?;
             }
-
-            break;
         }
     }
 
@@ -416,32 +1157,746 @@ pub fn get_changeset<'a>(fg: &mut FeatGraph<'a>, no_dev: bool) -> anyhow::Result
         }
     }
 
-    Ok(changed
+    let changes = changed
         .into_iter()
-        .map(|(pid, deps)| {
+        .map(|(pid, (deps, missing))| {
+            let names = |feats: &BTreeSet<NodeIndex>| {
+                feats
+                    .iter()
+                    .filter_map(|f| match fg.features[*f].fid()?.dep {
+                        Feat::Base => None,
+                        Feat::Named(name) => Some(name.to_string()),
+                    })
+                    .collect::<BTreeSet<_>>()
+            };
             let feats = deps
                 .into_iter()
                 .filter_map(|((ty, dep_pid), feats)| {
                     let package = fg.features[dep_pid].fid()?.pid;
-                    let feats = feats
-                        .iter()
-                        .filter_map(|f| match fg.features[*f].fid()?.dep {
-                            Feat::Base => None,
-                            Feat::Named(name) => Some(name.to_string()),
-                        })
-                        .collect::<BTreeSet<_>>();
+                    if is_proc_macro(package.package()) {
+                        warn!(
+                            "Unifying features of proc-macro crate {} for {}, \
+                            use --skip-proc-macro to leave them as is",
+                            package.package().name,
+                            pid.package().name
+                        );
+                    }
+                    let missing = missing.get(&(ty, dep_pid)).map(names).unwrap_or_default();
+                    let feats = names(&feats);
                     let rename = renames
                         .get(&pid)
-                        .map_or(false, |names| names.contains(&package.package().name));
+                        .is_some_and(|names| names.contains(&package.package().name));
                     Some(FeatChange {
                         pid: package,
                         ty,
                         rename,
                         features: feats,
+                        missing,
+                        declared: fg.patched.get(&package).copied(),
                     })
                 })
                 .collect::<Vec<_>>();
             (pid, feats)
         })
-        .collect::<BTreeMap<_, _>>())
+        .filter(|(_, feats)| !feats.is_empty())
+        .collect::<BTreeMap<_, _>>();
+    Ok((changes, compilations))
+}
+
+/// Order changes of every member by name, with `by_impact` members and their changes that add
+/// the most features go first
+pub fn sort_changes(changeset: &mut [(Pid, Vec<ChangePackage>)], by_impact: bool) {
+    for (_, changes) in changeset.iter_mut() {
+        changes.sort_by(|a, b| a.name.cmp(&b.name));
+        if by_impact {
+            // stable, changes with the same impact stay sorted by name
+            changes.sort_by_key(|change| Reverse(change.missing.len()));
+        }
+    }
+    if by_impact {
+        changeset.sort_by_key(|(_, changes)| {
+            let features = changes.iter().map(|c| c.missing.len()).sum::<usize>();
+            Reverse((features, changes.len()))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_changeset, hack, hack_changes, inactive_target_deps, members_to_hack, select_features,
+        sort_changes, stale_features, HackOptions, MemberConfig, Ty,
+    };
+    use crate::{
+        feat_graph::{
+            test::{copy_demo_workspace, get_demo_meta, get_demo_resolver},
+            FeatGraph, Pid,
+        },
+        metadata::{Resolver, Target},
+        opts::FeatureSelection,
+        source::ChangePackage,
+    };
+    use cargo_metadata::Metadata;
+    use cargo_platform::Cfg;
+    use std::{collections::BTreeSet, path::Path, str::FromStr};
+
+    #[test]
+    fn inactive_target_dependencies_are_listed() -> anyhow::Result<()> {
+        let meta = get_demo_meta(4)?;
+        let target = Target {
+            triple: "x86_64-unknown-linux-gnu".to_string(),
+            cfgs: vec![Cfg::from_str("target_family=\"unix\"")?],
+        };
+        let inactive = inactive_target_deps(&meta, &[target]);
+        assert_eq!(
+            inactive,
+            ["alpha -> beta (cfg(target_family = \"windows\"))"]
+        );
+        Ok(())
+    }
+
+    /// `cargo metadata` of a copied demo workspace, without touching the network
+    fn offline_meta(manifest_path: &Path) -> cargo_metadata::Result<Metadata> {
+        cargo_metadata::MetadataCommand::new()
+            .manifest_path(manifest_path)
+            .other_options(vec!["--offline".to_string()])
+            .exec()
+    }
+
+    /// Hack a workspace for linux
+    fn hack_linux(meta: &Metadata, opts: HackOptions) -> anyhow::Result<bool> {
+        hack(&opts, meta, vec![Target::from("x86_64-unknown-linux-gnu")])
+    }
+
+    /// Hack a copy of a demo workspace
+    fn hack_copy(dir: &tempfile::TempDir, name: &str, opts: HackOptions) -> anyhow::Result<bool> {
+        hack_linux(
+            &offline_meta(&dir.path().join(name).join("Cargo.toml"))?,
+            opts,
+        )
+    }
+
+    #[test]
+    fn hacking_is_reproducible() -> anyhow::Result<()> {
+        let manifests = |dir: &tempfile::TempDir| {
+            ["12/alpha/Cargo.toml", "12/beta/Cargo.toml"]
+                .map(|path| std::fs::read_to_string(dir.path().join(path)).unwrap())
+        };
+
+        let first = copy_demo_workspace(&["12", "8a"])?;
+        assert!(hack_copy(&first, "12", HackOptions::default())?);
+        let hacked = manifests(&first);
+
+        let second = copy_demo_workspace(&["12", "8a"])?;
+        assert!(hack_copy(&second, "12", HackOptions::default())?);
+        assert_eq!(hacked, manifests(&second));
+
+        for path in ["12/alpha/Cargo.toml", "12/beta/Cargo.toml"] {
+            crate::toml::restore_path(&first.path().join(path))?;
+        }
+        assert!(hack_copy(&first, "12", HackOptions::default())?);
+        assert_eq!(hacked, manifests(&first));
+        Ok(())
+    }
+
+    #[test]
+    fn no_banner_still_stashes() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["12", "8a"])?;
+        let path = dir.path().join("12/alpha/Cargo.toml");
+        let original = std::fs::read_to_string(&path)?;
+        assert!(hack_copy(
+            &dir,
+            "12",
+            HackOptions {
+                no_banner: true,
+                ..HackOptions::default()
+            },
+        )?);
+
+        let hacked = std::fs::read_to_string(&path)?;
+        assert!(!hacked.starts_with('#'), "{hacked}");
+        assert!(crate::toml::is_hacked(path.as_path().try_into()?)?);
+        assert!(crate::toml::restore_path(&path)?);
+        assert_eq!(original, std::fs::read_to_string(&path)?);
+        Ok(())
+    }
+
+    #[test]
+    fn virtual_root_is_left_alone() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["14", "14a"])?;
+        let root = dir.path().join("14/Cargo.toml");
+        let original = std::fs::read_to_string(&root)?;
+        assert!(hack_copy(&dir, "14", HackOptions::default())?);
+        assert_eq!(original, std::fs::read_to_string(&root)?);
+        assert!(crate::toml::is_hacked(
+            dir.path()
+                .join("14/alpha/Cargo.toml")
+                .as_path()
+                .try_into()?
+        )?);
+        assert!(!crate::toml::restore_path(&root)?);
+        assert_eq!(original, std::fs::read_to_string(&root)?);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_write_names_manifest() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["12", "8a"])?;
+        let meta = offline_meta(&dir.path().join("12/Cargo.toml"))?;
+        let alpha = dir.path().join("12/alpha/Cargo.toml");
+        // manifest changed after `cargo metadata` in a way `hack` can't handle
+        std::fs::write(&alpha, "[target.'cfg(unix)'.dependencies]\n")?;
+        let beta = dir.path().join("12/beta/Cargo.toml");
+        let original = std::fs::read_to_string(&beta)?;
+        let err = hack_linux(&meta, HackOptions::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Failed to update {}", alpha.display())
+        );
+        // nothing is written unless every member can be hacked
+        assert_eq!(std::fs::read_to_string(&beta)?, original);
+        Ok(())
+    }
+
+    #[test]
+    fn keep_going_hacks_the_rest() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["12", "8a"])?;
+        let meta = offline_meta(&dir.path().join("12/Cargo.toml"))?;
+        let alpha = dir.path().join("12/alpha/Cargo.toml");
+        std::fs::write(&alpha, "[target.'cfg(unix)'.dependencies]\n")?;
+        let report = dir.path().join("report.json");
+        let err = hack_linux(
+            &meta,
+            HackOptions {
+                keep_going: true,
+                report: Some(report.clone()),
+                ..HackOptions::default()
+            },
+        )
+        .unwrap_err();
+        let err = err.to_string();
+        assert!(
+            err.starts_with("Failed to update 1 of 2 manifests"),
+            "{err}"
+        );
+        assert!(err.contains("cargo hackerman restore"), "{err}");
+        assert!(err.contains(alpha.to_str().unwrap()), "{err}");
+
+        let beta = dir.path().join("12/beta/Cargo.toml");
+        assert!(crate::toml::is_hacked(beta.as_path().try_into()?)?);
+        let report = std::fs::read_to_string(report)?;
+        assert!(report.contains("beta"), "{report}");
+        Ok(())
+    }
+
+    #[test]
+    fn workspace_dependencies_are_unified() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["17", "17a"])?;
+        let manifests = ["17/Cargo.toml", "17/alpha/Cargo.toml", "17/beta/Cargo.toml"]
+            .map(|path| dir.path().join(path));
+        let read = || {
+            manifests
+                .each_ref()
+                .map(|p| std::fs::read_to_string(p).unwrap())
+        };
+        let original = read();
+        let opts = HackOptions {
+            workspace_deps: true,
+            ..HackOptions::default()
+        };
+        assert!(hack_linux(&offline_meta(&manifests[0])?, opts)?);
+
+        let hacked = read();
+        assert!(hacked[0].contains("gamma = { path = \"../17a/gamma\", features = [\"one\"] }"));
+        assert!(hacked[1].contains("gamma = { workspace = true }"));
+        assert_eq!(hacked[2], original[2]);
+
+        // members built on their own now see the unified features
+        let meta = offline_meta(&manifests[0])?;
+        let resolve = meta.resolve.as_ref().unwrap();
+        for node in &resolve.nodes {
+            let name = &meta[&node.id].name;
+            if name == "gamma" || name == "delta" {
+                assert_eq!(node.features.len(), 3, "{name}: {:?}", node.features);
+            }
+        }
+
+        for path in &manifests {
+            crate::toml::restore_path(path)?;
+        }
+        assert_eq!(read(), original);
+        Ok(())
+    }
+
+    #[test]
+    fn used_to_be_optional_and_now_required() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["16", "16a"])?;
+        let alpha = dir.path().join("16/alpha/Cargo.toml");
+        let original = std::fs::read_to_string(&alpha)?;
+        let err = hack_copy(&dir, "16", HackOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.ends_with("feature default of alpha refers to gamma as \"dep:gamma\""),
+            "{err}"
+        );
+        assert_eq!(original, std::fs::read_to_string(&alpha)?);
+        Ok(())
+    }
+
+    #[test]
+    fn patched_dependencies_keep_declared_source() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["19", "19a"])?;
+        assert!(hack_copy(&dir, "19", HackOptions::default())?);
+        for member in ["alpha", "beta"] {
+            let manifest =
+                std::fs::read_to_string(dir.path().join("19").join(member).join("Cargo.toml"))?;
+            // `[patch.crates-io]` only applies to dependencies declared from crates.io
+            assert!(
+                manifest.contains("omega = { version = \"0.1.0\", features = [\"one\", \"two\"] }"),
+                "{manifest}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn patch_matches_hacked_manifests() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["12", "8a"])?;
+        let manifests = ["alpha", "beta"].map(|m| dir.path().join("12").join(m).join("Cargo.toml"));
+        let read = || {
+            manifests
+                .each_ref()
+                .map(|p| std::fs::read_to_string(p).unwrap())
+        };
+        let original = read();
+
+        let meta = offline_meta(&dir.path().join("12/Cargo.toml"))?;
+        let patch = dir.path().join("changes.patch");
+        let hack_with = |patch| {
+            let opts = HackOptions {
+                patch,
+                ..HackOptions::default()
+            };
+            hack_linux(&meta, opts)
+        };
+        let opts = HackOptions {
+            patch: Some(patch.clone()),
+            report: Some(dir.path().join("report.json")),
+            ..HackOptions::default()
+        };
+        // nothing is applied with a patch, report would be empty
+        assert!(hack_linux(&meta, opts).is_err());
+        assert!(!patch.exists());
+
+        assert!(hack_with(Some(patch.clone()))?);
+        assert_eq!(read(), original);
+        let diff = std::fs::read_to_string(&patch)?;
+        assert!(
+            diff.starts_with("diff --git a/alpha/Cargo.toml b/alpha/Cargo.toml\n"),
+            "{diff}"
+        );
+        assert!(
+            diff.contains("diff --git a/beta/Cargo.toml b/beta/Cargo.toml\n"),
+            "{diff}"
+        );
+
+        assert!(hack_with(None)?);
+        let hacked = read();
+        for (path, original) in manifests.iter().zip(original) {
+            std::fs::write(path, original)?;
+        }
+        let status = std::process::Command::new("git")
+            .arg("apply")
+            .arg(&patch)
+            .current_dir(dir.path().join("12"))
+            .status()?;
+        assert!(status.success());
+        assert_eq!(read(), hacked);
+        Ok(())
+    }
+
+    #[test]
+    fn removed_features_of_hacked_dependencies_are_stale() {
+        // `cargo metadata` output after delta dropped feature "two" hackerman added to alpha
+        let err = "cargo metadata failed for 12/Cargo.toml
+
+Caused by:
+    error: failed to select a version for `delta`.
+        ... required by package `alpha v0.1.0 (/tmp/12/alpha)`
+    versions that meet the requirements `*` (locked to 0.1.0) are: 0.1.0
+
+    package `alpha` depends on `delta` with feature `two` but `delta` does not have that feature.
+     package `delta` does have feature `one`
+
+
+    failed to select a version for `delta` which could resolve this conflict";
+        assert_eq!(
+            stale_features(err),
+            ["in alpha: feature two of delta no longer exists; restore and re-hack"]
+        );
+        assert!(stale_features("error: could not find `Cargo.toml`").is_empty());
+    }
+
+    #[test]
+    fn report_lists_applied_changes() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["12", "8a"])?;
+        let report = dir.path().join("report.json");
+        assert!(hack_copy(
+            &dir,
+            "12",
+            HackOptions {
+                report: Some(report.clone()),
+                ..HackOptions::default()
+            },
+        )?);
+
+        let report = serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(report)?)?;
+        let manifests = report["manifests"].as_array().unwrap();
+        assert_eq!(manifests.len(), 2);
+        for manifest in manifests {
+            let changes = manifest["changes"].as_array().unwrap();
+            assert_eq!(changes.len(), 1);
+            assert_eq!(changes[0]["name"], "delta");
+            assert_eq!(changes[0]["ty"], "build");
+            assert_eq!(changes[0]["features"], serde_json::json!(["one", "two"]));
+        }
+        Ok(())
+    }
+
+    fn changed_features(no_default: bool) -> anyhow::Result<Vec<(String, BTreeSet<String>)>> {
+        let meta = get_demo_meta(11)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let (changes, _) = get_changeset(
+            &mut fg,
+            Resolver::V2,
+            &MemberConfig {
+                no_default,
+                ..MemberConfig::default()
+            },
+        )?;
+        Ok(changes
+            .into_iter()
+            .flat_map(|(member, changes)| {
+                changes
+                    .into_iter()
+                    .map(move |change| (member.package().name.clone(), change.features))
+            })
+            .collect())
+    }
+
+    #[test]
+    fn direct_only_skips_transitive_dependencies() -> anyhow::Result<()> {
+        let meta = get_demo_meta(13)?;
+        let changed = |direct_only| {
+            let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+            let (changes, _) = get_changeset(
+                &mut fg,
+                Resolver::V2,
+                &MemberConfig {
+                    direct_only,
+                    ..MemberConfig::default()
+                },
+            )?;
+            anyhow::Ok(
+                changes
+                    .into_iter()
+                    .flat_map(|(member, changes)| {
+                        changes.into_iter().map(move |change| {
+                            let dep = change.pid.package().name.clone();
+                            (member.package().name.clone(), dep)
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let pair = |member: &str, dep: &str| (member.to_string(), dep.to_string());
+        assert_eq!(
+            changed(false)?,
+            [pair("alpha", "zeta"), pair("beta", "epsilon")]
+        );
+        // zeta comes to alpha through epsilon
+        assert_eq!(changed(true)?, [pair("beta", "epsilon")]);
+        Ok(())
+    }
+
+    #[test]
+    fn changes_can_be_sorted_by_impact() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["23", "23a"])?;
+        let meta = offline_meta(&dir.path().join("23/Cargo.toml"))?;
+        let (mut changeset, _) = hack_changes(
+            &HackOptions::default(),
+            &meta,
+            vec![Target::from("x86_64-unknown-linux-gnu")],
+        )?;
+        let order = |changeset: &[(Pid, Vec<ChangePackage>)]| {
+            changeset
+                .iter()
+                .map(|(member, changes)| {
+                    let names = changes.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+                    (member.package().name.clone(), names.join(" "))
+                })
+                .collect::<Vec<_>>()
+        };
+        let pair = |a: &str, b: &str| (a.to_string(), b.to_string());
+
+        sort_changes(&mut changeset, false);
+        assert_eq!(
+            order(&changeset),
+            [pair("alpha", "delta eta"), pair("beta", "delta")]
+        );
+        // beta misses four features of delta, alpha misses one of delta and two of eta
+        sort_changes(&mut changeset, true);
+        assert_eq!(
+            order(&changeset),
+            [pair("beta", "delta"), pair("alpha", "eta delta")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn member_config_overrides_workspace() -> anyhow::Result<()> {
+        let mut meta = get_demo_meta(13)?;
+        for package in &mut meta.packages {
+            if package.name == "alpha" {
+                package.metadata = serde_json::json!({ "hackerman": { "direct-only": true } });
+            }
+        }
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let (changes, _) = get_changeset(&mut fg, Resolver::V2, &MemberConfig::default())?;
+        let members = changes
+            .keys()
+            .map(|member| member.package().name.as_str())
+            .collect::<Vec<_>>();
+        // zeta comes to alpha through epsilon, beta still unifies everything
+        assert_eq!(members, ["beta"]);
+        Ok(())
+    }
+
+    #[test]
+    fn build_dependencies_are_unified() -> anyhow::Result<()> {
+        let meta = get_demo_meta(12)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let (changes, compilations) =
+            get_changeset(&mut fg, Resolver::V2, &MemberConfig::default())?;
+        let feats = BTreeSet::from(["one", "two"].map(String::from));
+        assert_eq!(changes.len(), 2);
+        for change in changes.into_values().flatten() {
+            assert_eq!(change.ty, Ty::Build);
+            assert_eq!(change.features, feats);
+        }
+        assert_eq!((compilations.deps, compilations.before), (1, 3));
+        Ok(())
+    }
+
+    #[test]
+    fn normal_features_stay_out_of_build_dependencies() -> anyhow::Result<()> {
+        let meta = get_demo_meta(21)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let (changes, _) = get_changeset(&mut fg, Resolver::V2, &MemberConfig::default())?;
+        let changes = changes
+            .into_iter()
+            .flat_map(|(member, changes)| {
+                changes
+                    .into_iter()
+                    .map(move |change| (member.package().name.clone(), change.ty, change.features))
+            })
+            .collect::<Vec<_>>();
+        // gamma uses "three" of delta as a normal dependency only
+        let feats = BTreeSet::from(["one", "two"].map(String::from));
+        assert_eq!(
+            changes,
+            [
+                ("alpha".to_string(), Ty::Build, feats.clone()),
+                ("beta".to_string(), Ty::Build, feats),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skipped_proc_macro_features_do_not_spread() -> anyhow::Result<()> {
+        let meta = get_demo_meta(22)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let (changes, compilations) = get_changeset(
+            &mut fg,
+            Resolver::V2,
+            &MemberConfig {
+                skip_proc_macro: true,
+                ..MemberConfig::default()
+            },
+        )?;
+        let changes = changes
+            .into_iter()
+            .flat_map(|(member, changes)| {
+                changes.into_iter().map(move |change| {
+                    let dep = change.pid.package().name.clone();
+                    (member.package().name.clone(), dep, change.features)
+                })
+            })
+            .collect::<Vec<_>>();
+        // alpha and beta used to get "extra" of util through "x" of pm added to alpha but never
+        // written to its manifest, now alpha asks for it directly and beta gets it from alpha
+        let feats = BTreeSet::from(["extra".to_string()]);
+        assert_eq!(changes, [("alpha".to_string(), "util".to_string(), feats)]);
+        assert_eq!((compilations.deps, compilations.before), (1, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn dev_features_are_unified_according_to_resolver() -> anyhow::Result<()> {
+        let meta = get_demo_meta(14)?;
+        assert_eq!(get_demo_resolver(14)?, Resolver::V2);
+        let changes = |resolver| -> anyhow::Result<Vec<(String, Ty, BTreeSet<String>)>> {
+            let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+            let (changes, _) = get_changeset(&mut fg, resolver, &MemberConfig::default())?;
+            Ok(changes
+                .into_iter()
+                .flat_map(|(member, changes)| {
+                    changes.into_iter().map(move |change| {
+                        (member.package().name.clone(), change.ty, change.features)
+                    })
+                })
+                .collect())
+        };
+        let feats = |names: &[&str]| names.iter().map(|f| f.to_string()).collect();
+
+        // features of alpha's dev dependency stay with alpha
+        assert_eq!(
+            changes(Resolver::V2)?,
+            [("alpha".to_string(), Ty::Norm, feats(&["one"]))]
+        );
+        assert_eq!(
+            changes(Resolver::V1)?,
+            [
+                ("alpha".to_string(), Ty::Norm, feats(&["one", "testing"])),
+                ("beta".to_string(), Ty::Norm, feats(&["one", "testing"])),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn members_needing_unification_are_listed() -> anyhow::Result<()> {
+        let meta = get_demo_meta(14)?;
+        let members = members_to_hack(
+            false,
+            false,
+            &FeatureSelection::default(),
+            &meta,
+            get_demo_resolver(14)?,
+            vec![Target::from("x86_64-unknown-linux-gnu")],
+        )?;
+        let names = members
+            .keys()
+            .map(|path| path.parent().unwrap().file_name().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["alpha"]);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_features_are_listed_per_member() -> anyhow::Result<()> {
+        let meta = get_demo_meta(12)?;
+        let members = members_to_hack(
+            false,
+            false,
+            &FeatureSelection::default(),
+            &meta,
+            get_demo_resolver(12)?,
+            vec![Target::from("x86_64-unknown-linux-gnu")],
+        )?;
+        let feats =
+            |names: &[&str]| -> BTreeSet<String> { names.iter().map(|f| f.to_string()).collect() };
+        let missing = members
+            .iter()
+            .flat_map(|(path, changes)| {
+                let member = path.parent().unwrap().file_name().unwrap();
+                changes.iter().map(move |change| {
+                    let dep = &change.pid.package().name;
+                    (member, dep.as_str(), change.missing.clone())
+                })
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            missing,
+            [
+                ("alpha", "delta", feats(&["two"])),
+                ("beta", "delta", feats(&["one"])),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn selected_member_features_are_unified() -> anyhow::Result<()> {
+        let meta = get_demo_meta(14)?;
+        let selection = FeatureSelection {
+            features: vec!["beta/extra".to_string()],
+            ..FeatureSelection::default()
+        };
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        select_features(&mut fg, &selection)?;
+        let (changes, _) = get_changeset(&mut fg, Resolver::V2, &MemberConfig::default())?;
+        let changes = changes
+            .into_iter()
+            .flat_map(|(member, changes)| {
+                changes
+                    .into_iter()
+                    .map(move |change| (member.package().name.clone(), change.features))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            [(
+                "alpha".to_string(),
+                BTreeSet::from(["one", "testing"].map(String::from))
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn default_with_dep_syntax_brings_dependency_features() -> anyhow::Result<()> {
+        let meta = get_demo_meta(13)?;
+        let mut fg = FeatGraph::init(&meta, vec![Target::from("x86_64-unknown-linux-gnu")])?;
+        let (changes, _) = get_changeset(&mut fg, Resolver::V2, &MemberConfig::default())?;
+        let changes = changes
+            .into_iter()
+            .flat_map(|(member, changes)| {
+                changes.into_iter().map(move |change| {
+                    let name = &change.pid.package().name;
+                    (format!("{} {name}", member.package().name), change.features)
+                })
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            [
+                (
+                    "alpha zeta".to_string(),
+                    BTreeSet::from(["one", "two"].map(String::from))
+                ),
+                (
+                    "beta epsilon".to_string(),
+                    BTreeSet::from(["default".to_string()])
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn default_is_unified_as_any_feature() -> anyhow::Result<()> {
+        let feats = BTreeSet::from(["default", "extra", "std"].map(String::from));
+        assert_eq!(changed_features(false)?, [("beta".to_string(), feats)]);
+        Ok(())
+    }
+
+    #[test]
+    fn no_default_keeps_default_features_off() -> anyhow::Result<()> {
+        let feats = BTreeSet::from(["extra".to_string()]);
+        assert_eq!(changed_features(true)?, [("beta".to_string(), feats)]);
+        Ok(())
+    }
 }
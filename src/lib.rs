@@ -1,10 +1,18 @@
 #![doc = include_str!("../README.md")]
 
+pub mod analysis;
+pub mod cache;
+pub mod color;
+pub mod config;
 pub mod explain;
 pub mod feat_graph;
 pub mod hack;
 pub mod mergetool;
 pub mod metadata;
 pub mod opts;
+pub mod patch;
 pub mod source;
 pub mod toml;
+
+pub use analysis::{analyze, compute_unification, links_conflict, semver_groups, Analysis, Stats};
+pub use metadata::Target;
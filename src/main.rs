@@ -1,26 +1,27 @@
 use anyhow::Context;
 use cargo_hackerman::{
-    explain::{explain, tree},
-    feat_graph::{FeatGraph, Feature},
-    hack::hack,
-    mergetool,
+    analyze, color, config,
+    explain::{bloat_features, dependents, diff_features, explain, tree, why_feature},
+    feat_graph::{Feat, FeatGraph},
+    hack::{self, hack, hack_changes, members_to_hack, sort_changes, stale_features, HackOptions},
+    links_conflict, mergetool,
+    metadata::Resolver,
     opts::{self, Action},
-    toml,
+    semver_groups, toml, Target,
 };
-use cargo_metadata::camino::Utf8PathBuf;
+use cargo_metadata::camino::{Utf8Path, Utf8PathBuf};
 use cargo_platform::Cfg;
-use std::{
-    collections::{BTreeMap, BTreeSet},
-    process::Command,
-    str::FromStr,
-};
-use tracing::Level;
+use petgraph::graph::NodeIndex;
+use std::{collections::BTreeSet, io::IsTerminal, path::PathBuf, process::Command, str::FromStr};
+use tracing::{info, warn, Level};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
 fn start_subscriber((_, level): (usize, Level)) {
     let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| (EnvFilter::default().add_directive(level.into())));
+        .unwrap_or_else(|_| EnvFilter::default().add_directive(level.into()));
+    // stdout is reserved for the command output
     let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
         .with_target(false)
         .without_time()
         .with_level(false);
@@ -31,16 +32,104 @@ fn start_subscriber((_, level): (usize, Level)) {
         .init();
 }
 
-fn get_cfgs() -> anyhow::Result<Vec<Cfg>> {
-    let output = std::process::Command::new("rustc")
-        .arg("--print=cfg")
-        .output()
-        .context("rustc failed to run")?;
-    let stdout = String::from_utf8(output.stdout).unwrap();
-    Ok(stdout
-        .lines()
-        .map(Cfg::from_str)
-        .collect::<Result<Vec<_>, _>>()?)
+/// Targets along with cfg flags rustc sets for each of them, minus the ones user asked to
+/// pretend are not set
+fn get_targets(triples: &[String], excluded: &[String]) -> anyhow::Result<Vec<Target>> {
+    let mut targets = Vec::new();
+    for triple in triples {
+        let output = std::process::Command::new("rustc")
+            .arg("--print=cfg")
+            .arg("--target")
+            .arg(triple)
+            .output()
+            .context("rustc failed to run")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "rustc couldn't print cfg for target {triple}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let cfgs = stdout
+            .lines()
+            .map(Cfg::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        targets.push(Target {
+            triple: triple.clone(),
+            cfgs,
+        });
+    }
+    for exclude in excluded {
+        let cfg = Cfg::from_str(exclude).with_context(|| {
+            format!("{exclude:?} is not a valid cfg, try target_os=\"windows\"")
+        })?;
+        if targets.iter().any(|t| t.cfgs.contains(&cfg)) {
+            for target in &mut targets {
+                target.cfgs.retain(|c| *c != cfg);
+            }
+        } else {
+            warn!("{cfg} is not set for {triples:?}, nothing to exclude");
+        }
+    }
+    Ok(targets)
+}
+
+/// Print changes `hack` would make, fails if there are any
+///
+/// With `by_impact` members and changes adding the most features go first
+fn show_changes(
+    opts: &HackOptions,
+    metadata: &cargo_metadata::Metadata,
+    targets: Vec<Target>,
+    by_impact: bool,
+) -> anyhow::Result<()> {
+    let (mut changeset, compilations) = hack_changes(opts, metadata, targets)?;
+    if changeset.is_empty() {
+        println!("Features are unified as is");
+        return Ok(());
+    }
+    println!("Hackerman would like to set those features for following packets:");
+    sort_changes(&mut changeset, by_impact);
+    for (member, changes) in changeset {
+        println!("{}", member.package().manifest_path);
+        for change in changes {
+            let t = match change.ty {
+                hack::Ty::Dev => "dev ",
+                hack::Ty::Build => "build ",
+                hack::Ty::Norm => "",
+            };
+            print!(
+                "\t{} {} {}: {t}{:?}",
+                color::name(&change.name),
+                color::version(&change.version),
+                change.source,
+                change.feats
+            );
+            if change.missing.is_empty() {
+                println!();
+            } else {
+                println!(", missing {:?}", change.missing);
+            }
+        }
+    }
+    println!("{compilations}");
+    anyhow::bail!("Features are not unified");
+}
+
+/// `cargo metadata` for `check`, features members ask for that their dependencies no longer
+/// have are listed on top of the cargo error
+fn check_metadata(
+    profile: &opts::Profile,
+    selection: &opts::FeatureSelection,
+) -> anyhow::Result<cargo_metadata::Metadata> {
+    profile.exec_with(selection).map_err(|err| {
+        let stale = stale_features(&format!("{err:#}"));
+        if stale.is_empty() {
+            err
+        } else {
+            err.context(stale.join("\n"))
+        }
+    })
 }
 
 fn main() -> anyhow::Result<()> {
@@ -50,67 +139,274 @@ fn main() -> anyhow::Result<()> {
         Action::Hack {
             profile,
             dry,
+            sort_by_impact,
+            quiet,
             lock,
             no_dev,
+            no_default,
+            rename,
+            skip_proc_macro,
+            direct_only,
+            workspace_deps,
+            keep_going,
+            all_workspaces,
+            report,
+            patch,
+            no_banner,
+            selection,
         } => {
             start_subscriber(profile.verbosity);
-            let metadata = profile.exec()?;
-            let platform = target_spec::Platform::current()?;
-            let triplets = vec![platform.triple_str()];
-            let cfgs = get_cfgs()?;
-            hack(dry, lock, no_dev, &metadata, triplets, cfgs)?;
-
-            // regenerate Cargo.lock file
-            if !dry {
-                profile.exec()?;
+            color::init(profile.color);
+            hack::show_progress(!quiet && std::io::stderr().is_terminal());
+            if all_workspaces && (report.is_some() || patch.is_some()) {
+                anyhow::bail!(
+                    "--report and --patch record a single workspace, \
+                    they can't be used with --all-workspaces"
+                );
+            }
+            if report.is_some() && patch.is_some() {
+                anyhow::bail!(
+                    "--patch leaves the manifests unchanged so there is nothing to --report, \
+                    use one or the other"
+                );
+            }
+            let profiles = if all_workspaces {
+                workspace_profiles(&profile)?
+            } else {
+                vec![profile]
+            };
+            let opts = HackOptions {
+                lock,
+                no_dev,
+                no_default,
+                rename,
+                skip_proc_macro,
+                direct_only,
+                workspace_deps,
+                keep_going,
+                report,
+                patch,
+                no_banner,
+                selection,
+            };
+            for profile in &profiles {
+                if all_workspaces {
+                    info!("Hacking workspace at {}", profile.manifest_path.display());
+                }
+                let metadata = profile.exec_with(&opts.selection)?;
+                let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+                if dry {
+                    show_changes(&opts, &metadata, targets, sort_by_impact)?;
+                    continue;
+                }
+                let changed = hack(&opts, &metadata, targets)?;
+
+                // regenerate Cargo.lock file, manifests stay the same if there's nothing to unify
+                // or if the changes go to a patch
+                if opts.patch.is_none() && changed {
+                    if let Err(err) = profile.regenerate_lock() {
+                        if profile.locked || profile.frozen {
+                            // Cargo.lock is not allowed to change, leave the workspace as it was
+                            let members =
+                                metadata.workspace_members.iter().collect::<BTreeSet<_>>();
+                            for package in &metadata.packages {
+                                if members.contains(&package.id) {
+                                    toml::restore(&package.manifest_path)?;
+                                }
+                            }
+                            toml::restore(&metadata.workspace_root.join("Cargo.toml"))?;
+                            return Err(err.context(
+                                "Unified dependencies need Cargo.lock changes, manifests are restored",
+                            ));
+                        }
+                        return Err(
+                            err.context("Manifests were changed but Cargo.lock was not updated")
+                        );
+                    }
+                }
             }
         }
 
-        Action::Restore { profile, separate } => {
+        Action::Restore {
+            profile,
+            dry,
+            force,
+            separate,
+        } => {
             start_subscriber(profile.verbosity);
+            let restore = |manifest: &Utf8Path| {
+                if dry {
+                    let changed = toml::needs_restore(manifest)?;
+                    if changed {
+                        println!("Would restore {manifest}");
+                    }
+                    Ok(changed)
+                } else if force {
+                    toml::restore_forced(manifest)
+                } else {
+                    toml::restore(manifest)
+                }
+            };
             let mut changed = false;
             if separate.is_empty() {
                 let metadata = profile.exec()?;
                 let members = metadata.workspace_members.iter().collect::<BTreeSet<_>>();
+                let mut restored = BTreeSet::new();
                 for package in &metadata.packages {
                     if members.contains(&package.id) {
-                        changed |= toml::restore(&package.manifest_path)?;
+                        changed |= restore(&package.manifest_path)?;
+                        restored.insert(package.manifest_path.clone());
+                    }
+                }
+                // `hack --workspace-deps` changes the root even when it is not a member
+                let root = metadata.workspace_root.join("Cargo.toml");
+                if !restored.contains(&root) {
+                    changed |= restore(&root)?;
+                    restored.insert(root.clone());
+                }
+
+                // crates excluded from the workspace while hacked are left behind otherwise,
+                // only the ones `[workspace] members` still points to are looked at
+                for pattern in toml::member_patterns(&root)? {
+                    let pattern = metadata.workspace_root.join(pattern).join("Cargo.toml");
+                    for manifest in glob::glob(pattern.as_str())? {
+                        let manifest = Utf8PathBuf::try_from(manifest?)?;
+                        if restored.contains(&manifest) {
+                            continue;
+                        }
+                        match toml::is_hacked(&manifest) {
+                            Ok(true) => {
+                                warn!(
+                                    "{manifest} is hacked but is not a workspace member, restoring"
+                                );
+                                changed |= restore(&manifest)?;
+                            }
+                            Ok(false) => {}
+                            Err(err) => warn!("Couldn't check if {manifest} is hacked: {err:#}"),
+                        }
                     }
                 }
             } else {
                 for path in separate {
-                    let utf8_path = Utf8PathBuf::try_from(path)?;
-                    changed |= toml::restore(&utf8_path)?;
+                    for manifest in expand_manifests(path)? {
+                        changed |= restore(&manifest)?;
+                    }
+                }
+            }
+            if dry {
+                if !changed {
+                    println!("Nothing to restore");
+                }
+            } else if changed {
+                profile.regenerate_lock()?;
+            }
+        }
+
+        Action::Check {
+            profile,
+            no_dev,
+            no_default,
+            json: true,
+            selection,
+        } => {
+            start_subscriber(profile.verbosity);
+            let metadata = check_metadata(&profile, &selection)?;
+            let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+            let resolver = Resolver::of_workspace(&metadata)?;
+            let to_hack =
+                members_to_hack(no_dev, no_default, &selection, &metadata, resolver, targets)?;
+
+            let members = metadata.workspace_members.iter().collect::<BTreeSet<_>>();
+            let mut passed = true;
+            let mut report = Vec::new();
+            for package in &metadata.packages {
+                if !members.contains(&package.id) {
+                    continue;
                 }
+                let checksum = toml::verify_checksum(package.manifest_path.as_std_path());
+                let changes = to_hack.get(package.manifest_path.as_path());
+                let needs_hack = changes.is_some();
+                passed &= checksum.is_ok() && !needs_hack;
+                let missing = changes
+                    .into_iter()
+                    .flatten()
+                    .map(|change| {
+                        let package = change.pid.package();
+                        serde_json::json!({
+                            "name": package.name,
+                            "version": package.version.to_string(),
+                            "ty": change.ty.to_string(),
+                            "features": change.missing,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                report.push(serde_json::json!({
+                    "name": package.name,
+                    "manifest": package.manifest_path,
+                    "checksum_ok": checksum.is_ok(),
+                    "checksum_error": checksum.err().map(|e| format!("{e:#}")),
+                    "needs_unification": needs_hack,
+                    "missing": missing,
+                }));
             }
-            if changed {
-                // regenerate Cargo.lock file
-                profile.exec()?;
+            let report = serde_json::json!({ "passed": passed, "members": report });
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !passed {
+                anyhow::bail!("Workspace check failed");
             }
         }
 
-        Action::Check { profile, no_dev } => {
+        Action::Check {
+            profile,
+            no_dev,
+            no_default,
+            json: false,
+            selection,
+        } => {
             start_subscriber(profile.verbosity);
-            let metadata = profile.exec()?;
+            color::init(profile.color);
+            let metadata = check_metadata(&profile, &selection)?;
             let members = metadata.workspace_members.iter().collect::<BTreeSet<_>>();
             for package in &metadata.packages {
                 if members.contains(&package.id) {
                     toml::verify_checksum(package.manifest_path.as_std_path())?;
                 }
             }
-            let platform = target_spec::Platform::current()?;
-            let triplets = vec![platform.triple_str()];
-            let cfgs = get_cfgs()?;
-            hack(true, false, no_dev, &metadata, triplets, cfgs)?;
+            let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+            let opts = HackOptions {
+                no_dev,
+                no_default,
+                selection,
+                ..HackOptions::default()
+            };
+            show_changes(&opts, &metadata, targets, false)?;
         }
 
         Action::MergeDriver {
+            check,
             base,
             local,
             remote,
             result,
         } => {
-            mergetool::merge(&base, &local, &remote, &result)?;
+            let code = mergetool::merge(&base, &local, &remote, &result)?;
+            if check && code == 0 {
+                let targets =
+                    get_targets(&config::default_targets(&std::env::current_dir()?)?, &[])?;
+                match mergetool::needs_hack(&result, &local, targets) {
+                    Ok(true) => eprintln!(
+                        "hackerman: features are no longer unified after merging {}, \
+                        run `cargo hackerman hack` again",
+                        result.display()
+                    ),
+                    Ok(false) => {}
+                    Err(err) => eprintln!(
+                        "hackerman: unable to check unification after merging {}: {err:#}",
+                        result.display()
+                    ),
+                }
+            }
+            std::process::exit(code);
         }
         Action::Tree {
             profile,
@@ -118,18 +414,28 @@ fn main() -> anyhow::Result<()> {
             package_nodes,
             workspace,
             krate,
-            feature,
-            version,
+            feature_version: (feature, version),
             no_dev,
+            optional_only,
+            duplicates_only,
+            selection,
             stdout,
+            format,
+            layout,
         } => {
             start_subscriber(profile.verbosity);
+            let (krate, version) = match krate {
+                Some(krate) => {
+                    let (krate, version) = opts::package_id_spec(&krate, version)?;
+                    (Some(krate), version)
+                }
+                None => (None, version),
+            };
             let metadata = profile.exec()?;
-            let platform = target_spec::Platform::current()?;
-            let triplets = vec![platform.triple_str()];
-            let cfgs = get_cfgs()?;
-            let mut fg = FeatGraph::init(&metadata, triplets, cfgs)?;
+            let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+            let mut fg = FeatGraph::init(&metadata, targets)?;
             fg.optimize(no_transitive_opt)?;
+            fg.graph_attrs.extend(layout.graph_attrs());
             tree(
                 &mut fg,
                 krate.as_ref(),
@@ -138,26 +444,35 @@ fn main() -> anyhow::Result<()> {
                 package_nodes,
                 workspace,
                 no_dev,
+                optional_only,
+                duplicates_only,
+                &selection,
                 stdout,
+                format,
             )?;
         }
 
         Action::Explain {
             profile,
             krate,
-            feature,
-            version,
+            feature_version: (feature, version),
             no_transitive_opt,
             package_nodes,
+            features_only,
             stdout,
+            kind,
+            no_dev,
+            until,
+            format,
+            layout,
         } => {
             start_subscriber(profile.verbosity);
+            let (krate, version) = opts::package_id_spec(&krate, version)?;
             let metadata = profile.exec()?;
-            let platform = target_spec::Platform::current()?;
-            let triplets = vec![platform.triple_str()];
-            let cfgs = get_cfgs()?;
-            let mut fg = FeatGraph::init(&metadata, triplets, cfgs)?;
+            let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+            let mut fg = FeatGraph::init(&metadata, targets)?;
             fg.optimize(no_transitive_opt)?;
+            fg.graph_attrs.extend(layout.graph_attrs());
 
             explain(
                 &mut fg,
@@ -165,27 +480,145 @@ fn main() -> anyhow::Result<()> {
                 feature.as_ref(),
                 version.as_ref(),
                 package_nodes,
+                features_only,
                 stdout,
+                kind,
+                no_dev,
+                until.as_deref(),
+                format,
             )?;
         }
+        Action::Dependents {
+            profile,
+            krate,
+            feature_version: (feature, version),
+        } => {
+            start_subscriber(profile.verbosity);
+            let (krate, version) = opts::package_id_spec(&krate, version)?;
+            let metadata = profile.exec()?;
+            let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+            let mut fg = FeatGraph::init(&metadata, targets)?;
+
+            let chains = dependents(&mut fg, &krate, feature.as_ref(), version.as_ref())?;
+            for chain in &chains {
+                let labels = chain
+                    .iter()
+                    .map(|&ix| node_label(&fg, ix))
+                    .collect::<Vec<_>>();
+                println!("{}", labels.join(" -> "));
+            }
+            if chains.is_empty() {
+                println!("No workspace members depend on {krate}");
+            }
+        }
+        Action::WhyFeature {
+            profile,
+            krate,
+            feature,
+            version,
+        } => {
+            start_subscriber(profile.verbosity);
+            let (krate, version) = opts::package_id_spec(&krate, version)?;
+            let metadata = profile.exec()?;
+            let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+            let mut fg = FeatGraph::init(&metadata, targets)?;
+
+            let chains = why_feature(&mut fg, &krate, &feature, version.as_ref())?;
+            for chain in &chains {
+                let labels = chain
+                    .iter()
+                    .map(|step| match step.weak_dep {
+                        Some(dep) => format!(
+                            "{} (if {} is enabled)",
+                            node_label(&fg, step.node),
+                            node_label(&fg, dep)
+                        ),
+                        None => node_label(&fg, step.node),
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", labels.join(" -> "));
+            }
+            if chains.is_empty() {
+                println!("No workspace members enable {krate}:{feature}");
+            }
+        }
+        Action::DiffFeatures {
+            profile,
+            krate,
+            old,
+            new,
+        } => {
+            start_subscriber(profile.verbosity);
+            let metadata = profile.exec()?;
+            let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+            let mut fg = FeatGraph::init(&metadata, targets)?;
+
+            let diff = diff_features(&mut fg, &krate, &old, &new)?;
+            for name in &diff.added {
+                println!("+ {name}");
+            }
+            for name in &diff.removed {
+                println!("- {name}");
+            }
+            for (name, old_feats, new_feats) in &diff.changed {
+                println!("~ {name}: {old_feats:?} -> {new_feats:?}");
+            }
+            if diff.is_empty() {
+                println!("{krate} {old} and {new} declare the same features");
+            }
+        }
         Action::ShowCrate {
             profile,
             krate,
             version,
             focus,
         } => {
+            start_subscriber(profile.verbosity);
+            let (krate, version) = opts::package_id_spec(&krate, version)?;
             let metadata = profile.exec()?;
             let version = version.map(|v| v.to_string());
-            let package = metadata
-                .packages
-                .iter()
-                .find(|p| {
-                    p.name == krate
-                        && version
-                            .as_ref()
-                            .map_or(true, |v| &p.version.to_string() == v)
-                })
-                .ok_or_else(|| anyhow::anyhow!("{krate} {version:?} is not used"))?;
+            let mut matching = metadata.packages.iter().filter(|p| {
+                p.name == krate && version.as_ref().is_none_or(|v| &p.version.to_string() == v)
+            });
+
+            if let opts::Focus::Versions = focus {
+                let mut versions = matching.collect::<Vec<_>>();
+                versions.sort_by(|a, b| a.version.cmp(&b.version));
+                for package in &versions {
+                    match &package.source {
+                        Some(source) => println!("{} {} {source}", package.name, package.version),
+                        None => println!(
+                            "{} {} {}",
+                            package.name,
+                            package.version,
+                            package
+                                .manifest_path
+                                .parent()
+                                .unwrap_or(&package.manifest_path)
+                        ),
+                    }
+                }
+                if versions.is_empty() {
+                    anyhow::bail!("{krate} {version:?} is not used");
+                }
+                return Ok(());
+            }
+
+            let Some(package) = matching.next() else {
+                // docs.rs and crates.io pages can be found by name alone
+                let url = match (&focus, &version) {
+                    (opts::Focus::Documentation, Some(v)) => format!("https://docs.rs/{krate}/{v}"),
+                    (opts::Focus::Documentation, None) => format!("https://docs.rs/{krate}/latest"),
+                    (opts::Focus::Repository, Some(v)) => {
+                        format!("https://crates.io/crates/{krate}/{v}")
+                    }
+                    (opts::Focus::Repository, None) => format!("https://crates.io/crates/{krate}"),
+                    _ => anyhow::bail!("{krate} {version:?} is not used"),
+                };
+                warn!("{krate} is not used by the workspace, opening {url}");
+                open_url(&url)?;
+                return Ok(());
+            };
 
             match focus {
                 opts::Focus::Manifest => {
@@ -199,6 +632,14 @@ fn main() -> anyhow::Result<()> {
                     println!("{manifest}");
                     return Ok(());
                 }
+                opts::Focus::Stash => {
+                    let path = &package.manifest_path;
+                    match toml::unhacked(path)? {
+                        Some(manifest) => print!("{manifest}"),
+                        None => anyhow::bail!("{path} is not hacked, nothing is stashed"),
+                    }
+                    return Ok(());
+                }
                 opts::Focus::Readme => {
                     let manifest = &package.manifest_path;
                     if let Some(readme) = &package.readme {
@@ -223,53 +664,233 @@ fn main() -> anyhow::Result<()> {
                         anyhow::bail!("Package {krate} v{} defines no repository", package.version);
                     }
                 }
+                opts::Focus::Licenses => {
+                    if package.license.is_none() && package.license_file.is_none() {
+                        anyhow::bail!(
+                            "Package {krate} v{} defines neither license nor license-file",
+                            package.version
+                        );
+                    }
+                    if let Some(license) = &package.license {
+                        println!("{license}");
+                    }
+                    if let Some(file) = &package.license_file {
+                        let path = package.manifest_path.with_file_name(file);
+                        println!(
+                            "{}",
+                            std::fs::read_to_string(&path).with_context(|| {
+                                format!("Failed to read license file {path}")
+                            })?
+                        );
+                    }
+                }
+                opts::Focus::Versions => unreachable!("handled above"),
             }
         }
-        Action::Dupes { profile } => {
-            let mut any = false;
+        Action::Dupes {
+            profile,
+            normal_only,
+            semver,
+        } => {
+            start_subscriber(profile.verbosity);
+            color::init(profile.color);
             let metadata = profile.exec()?;
-            let platform = target_spec::Platform::current()?;
-            let triplets = vec![platform.triple_str()];
-            let cfgs = get_cfgs()?;
-            let mut fg = FeatGraph::init(&metadata, triplets, cfgs)?;
-            fg.shrink_to_target()?;
+            let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+            let analysis = analyze(&metadata, targets)?;
 
-            let mut packages = BTreeMap::new();
-            for fid in fg.features.node_weights().filter_map(Feature::fid) {
-                if fid == fid.get_base() {
-                    let p = fid.pid.package();
-                    packages
-                        .entry(p.name.clone())
-                        .or_insert_with(Vec::new)
-                        .push(p.clone());
+            let duplicates = if normal_only {
+                analysis.normal_duplicates()
+            } else {
+                analysis.duplicates()
+            };
+            for (name, copies) in &duplicates {
+                print!("{}:", color::name(name));
+                for c in copies {
+                    print!(" {}", color::version(&c.version));
                 }
-            }
-            for (name, copies) in &packages {
-                if copies.len() < 2 {
-                    continue;
+                if semver {
+                    let groups = semver_groups(copies);
+                    if groups.len() == 1 {
+                        print!(" (semver compatible)");
+                    } else if groups.iter().all(|g| g.len() == 1) {
+                        print!(" (incompatible)");
+                    } else {
+                        for group in groups.iter().filter(|g| g.len() > 1) {
+                            let versions = group
+                                .iter()
+                                .map(|p| p.version.to_string())
+                                .collect::<Vec<_>>();
+                            print!(" (compatible: {})", versions.join(" "));
+                        }
+                    }
                 }
-                any = true;
-                print!("{name}:");
-                for c in copies {
-                    print!(" {}", c.version);
+                if let Some(links) = links_conflict(copies) {
+                    print!(
+                        " {}",
+                        color::error(format!("(will not compile: links = {links:?})"))
+                    );
                 }
                 println!();
             }
-            if !any {
+            if duplicates.is_empty() {
                 println!("All packages are present in one version only");
             }
         }
+        Action::Cycles { profile } => {
+            start_subscriber(profile.verbosity);
+            let metadata = profile.exec()?;
+            let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+            let mut fg = FeatGraph::init(&metadata, targets)?;
+            fg.shrink_to_target()?;
+
+            let cycles = fg.cycles();
+            for cycle in &cycles {
+                let packages = cycle
+                    .iter()
+                    .filter_map(|&ix| fg.features[ix].pid())
+                    .map(|pid| format!("{} {}", pid.package().name, pid.package().version))
+                    .collect::<BTreeSet<_>>();
+                println!(
+                    "Cycle between {}:",
+                    packages.into_iter().collect::<Vec<_>>().join(", ")
+                );
+                for fid in cycle.iter().filter_map(|&ix| fg.features[ix].fid()) {
+                    let package = fid.pid.package();
+                    println!("\t{} {} {}", package.name, package.version, fid.dep);
+                }
+            }
+            if cycles.is_empty() {
+                println!("No dependency cycles found");
+            }
+        }
+        Action::BloatFeatures { profile, crates } => {
+            start_subscriber(profile.verbosity);
+            let metadata = profile.exec()?;
+            let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+            let mut fg = FeatGraph::init(&metadata, targets)?;
+
+            let ranked = bloat_features(&mut fg)?;
+            for bloat in &ranked {
+                println!("{:>5} {}", bloat.added.len(), node_label(&fg, bloat.node));
+                if crates {
+                    for pid in &bloat.added {
+                        let package = pid.package();
+                        println!("\t{} {}", package.name, package.version);
+                    }
+                }
+            }
+            if ranked.is_empty() {
+                println!("No enabled features bring in extra crates");
+            }
+        }
+
+        Action::GraphStats { profile } => {
+            start_subscriber(profile.verbosity);
+            let metadata = profile.exec()?;
+            let targets = get_targets(&profile.targets()?, &profile.exclude_cfg)?;
+            let stats = analyze(&metadata, targets)?.stats();
+            println!("crates: {}", stats.crates);
+            println!("features: {}", stats.features);
+            println!("duplicates: {}", stats.duplicates);
+            println!("depth: {}", stats.depth);
+            println!("members: {}", stats.members);
+        }
     }
     Ok(())
 }
 
+/// `name version` for base nodes, `name version:feature` for named features
+fn node_label(fg: &FeatGraph, ix: NodeIndex) -> String {
+    let fid = fg.features[ix]
+        .fid()
+        .expect("workspace and crate nodes have fid");
+    let package = fid.pid.package();
+    match fid.dep {
+        Feat::Base => format!("{} {}", package.name, package.version),
+        Feat::Named(name) => format!("{} {}:{name}", package.name, package.version),
+    }
+}
+
+/// Expand a path given by user into a list of manifests: directories are searched for
+/// `Cargo.toml` files recursively, glob patterns are expanded
+fn expand_manifests(path: PathBuf) -> anyhow::Result<Vec<Utf8PathBuf>> {
+    fn walk(dir: &Utf8Path, res: &mut Vec<Utf8PathBuf>) -> anyhow::Result<()> {
+        for entry in dir.read_dir_utf8()? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                if entry.file_name() != "target" && !entry.file_name().starts_with('.') {
+                    walk(path, res)?;
+                }
+            } else if entry.file_name() == "Cargo.toml" {
+                res.push(path.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    let path = Utf8PathBuf::try_from(path)?;
+    let mut res = Vec::new();
+    if path.is_dir() {
+        walk(&path, &mut res)?;
+    } else if path.exists() {
+        res.push(path);
+    } else {
+        for entry in glob::glob(path.as_str())? {
+            let entry = Utf8PathBuf::try_from(entry?)?;
+            if entry.is_dir() {
+                walk(&entry, &mut res)?;
+            } else {
+                res.push(entry);
+            }
+        }
+        if res.is_empty() {
+            anyhow::bail!("{path} doesn't match any files");
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// A copy of `profile` for every workspace found under its `--manifest-path` directory
+fn workspace_profiles(profile: &opts::Profile) -> anyhow::Result<Vec<opts::Profile>> {
+    if profile.metadata_from.is_some() {
+        anyhow::bail!(
+            "--all-workspaces needs to run cargo metadata, --metadata-from can't be used"
+        );
+    }
+    let root = match profile.manifest_path.parent() {
+        // default `Cargo.toml` or a manifest file, search next to it
+        Some(parent) if !profile.manifest_path.is_dir() => parent.to_path_buf(),
+        _ => profile.manifest_path.clone(),
+    };
+    let root = if root.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        root
+    };
+    let mut profiles = Vec::new();
+    for manifest in expand_manifests(root.clone())? {
+        if toml::is_workspace_root(&manifest)? {
+            profiles.push(opts::Profile {
+                manifest_path: manifest.into_std_path_buf(),
+                ..profile.clone()
+            });
+        }
+    }
+    if profiles.is_empty() {
+        anyhow::bail!("No workspaces found in {}", root.display());
+    }
+    Ok(profiles)
+}
+
 fn open_url(url: &str) -> anyhow::Result<()> {
     if cfg!(target_os = "linux") {
         Command::new("xdg-open").arg(url).output()?;
     } else if cfg!(target_os = "windows") {
         Command::new("start").arg(url).output()?;
     } else {
-        #[cfg(feature = "webbroser")]
+        #[cfg(feature = "webbrowser")]
         {
             webbrowser::open(url)?;
             return Ok(());
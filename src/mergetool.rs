@@ -1,4 +1,10 @@
-use crate::toml::restore;
+use crate::{
+    hack::members_to_hack,
+    metadata::{Resolver, Target},
+    opts::FeatureSelection,
+    toml::restore,
+};
+use anyhow::Context;
 use cargo_metadata::camino::Utf8PathBuf;
 use std::path::Path;
 
@@ -10,10 +16,18 @@ fn restore_path(path: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn merge(base: &Path, local: &Path, remote: &Path, _merged: &Path) -> anyhow::Result<()> {
-    restore_path(local)?;
-    restore_path(base)?;
-    restore_path(remote)?;
+/// Restore all three versions and merge them with `git merge-file`, result goes into `local`
+///
+/// Returns `git merge-file` exit code: 0 for a clean merge, number of conflicts otherwise
+pub fn merge(base: &Path, local: &Path, remote: &Path, _merged: &Path) -> anyhow::Result<i32> {
+    // merge driver runs in the middle of git operations, one broken side should not stop it,
+    // unrestored side is merged as is. Without base there's nothing to merge against.
+    restore_path(base).context("Failed to restore base version of Cargo.toml")?;
+    for (side, path) in [("local", local), ("remote", remote)] {
+        if let Err(err) = restore_path(path) {
+            eprintln!("hackerman: failed to restore {side} version of Cargo.toml, merging it as is: {err:#}");
+        }
+    }
 
     let output = std::process::Command::new("git")
         .arg("merge-file")
@@ -29,5 +43,137 @@ pub fn merge(base: &Path, local: &Path, remote: &Path, _merged: &Path) -> anyhow
 
     std::fs::write(local, merged_bytes)?;
 
-    std::process::exit(code.code().unwrap_or(-1));
+    Ok(code.code().unwrap_or(-1))
+}
+
+/// Check if workspace containing `path` needs unification once `merged` replaces it
+///
+/// Workspace configuration applies the same way as for `hack` with default feature selection.
+/// `path` is restored to its original contents afterwards. Cargo runs with `--locked` and
+/// `--offline` so the check never touches `Cargo.lock` or the network.
+pub fn needs_hack(path: &Path, merged: &Path, targets: Vec<Target>) -> anyhow::Result<bool> {
+    let original = std::fs::read(path).ok();
+    std::fs::copy(merged, path)?;
+    let res = (|| {
+        let meta = cargo_metadata::MetadataCommand::new()
+            .manifest_path(path)
+            .other_options(vec!["--locked".to_string(), "--offline".to_string()])
+            .exec()?;
+        let resolver = Resolver::of_workspace(&meta)?;
+        let selection = FeatureSelection::default();
+        let changes = members_to_hack(false, false, &selection, &meta, resolver, targets)?;
+        Ok(!changes.is_empty())
+    })();
+    match original {
+        Some(contents) => std::fs::write(path, contents)?,
+        None => std::fs::remove_file(path)?,
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, needs_hack};
+    use crate::{feat_graph::test::copy_demo_workspace, metadata::Target};
+    use std::path::PathBuf;
+
+    const BASE: &str = "[package]\nname = \"alpha\"\n\n[dependencies]\nbeta = \"1.0\"\n";
+
+    fn write_all(dir: &tempfile::TempDir, local: &str, remote: &str) -> [PathBuf; 3] {
+        let paths = ["base", "local", "remote"].map(|name| dir.path().join(name));
+        for (path, contents) in paths.iter().zip([BASE, local, remote]) {
+            std::fs::write(path, contents).unwrap();
+        }
+        paths
+    }
+
+    #[test]
+    fn merge_restores_hacked_sides() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let local = "[package]\nname = \"alpha\"\n\n[dependencies]\n\
+            beta = { version = \"1.0\", features = [\"one\"] }\n\n\
+            [package.metadata.hackerman.stash.dependencies]\nbeta = \"1.0\"\n";
+        let remote = format!("{BASE}gamma = \"2.0\"\n");
+        let [base, local, remote] = write_all(&dir, local, &remote);
+
+        assert_eq!(merge(&base, &local, &remote, &local)?, 0);
+        let merged = std::fs::read_to_string(&local)?;
+        assert!(
+            merged.contains("beta = \"1.0\"\ngamma = \"2.0\"\n"),
+            "{merged}"
+        );
+        assert!(!merged.contains("hackerman"), "{merged}");
+        Ok(())
+    }
+
+    #[test]
+    fn merge_survives_broken_side() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let broken = format!("{BASE}\n[package.metadata.hackerman.stash]\ndependencies = 1\n");
+        let [base, local, remote] = write_all(&dir, &broken, BASE);
+
+        assert_eq!(merge(&base, &local, &remote, &local)?, 0);
+        assert_eq!(std::fs::read_to_string(&local)?, broken);
+
+        let [base, local, remote] = write_all(&dir, BASE, BASE);
+        std::fs::write(&base, &broken)?;
+        assert!(merge(&base, &local, &remote, &local).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merged_manifest_is_checked_in_place() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["12", "8a"])?;
+        let path = dir.path().join("12/alpha/Cargo.toml");
+        let original = std::fs::read_to_string(&path)?;
+        let merged = dir.path().join("merged");
+        let targets = || vec![Target::from("x86_64-unknown-linux-gnu")];
+
+        std::fs::write(&merged, &original)?;
+        assert!(needs_hack(&path, &merged, targets())?);
+        assert_eq!(std::fs::read_to_string(&path)?, original);
+
+        // once both members use both features there's nothing left to unify
+        let beta = dir.path().join("12/beta/Cargo.toml");
+        let unified = std::fs::read_to_string(&beta)?.replace("\"two\"", "\"one\", \"two\"");
+        std::fs::write(&beta, unified)?;
+        std::fs::write(&merged, original.replace("\"one\"", "\"one\", \"two\""))?;
+        assert!(!needs_hack(&path, &merged, targets())?);
+        Ok(())
+    }
+
+    #[test]
+    fn merged_manifest_is_checked_with_workspace_config() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["14", "14a"])?;
+        let path = dir.path().join("14/alpha/Cargo.toml");
+        let merged = dir.path().join("merged");
+        let targets = || vec![Target::from("x86_64-unknown-linux-gnu")];
+
+        // alpha uses gamma in tests only, it is missing "one" from beta there
+        let original = std::fs::read_to_string(&path)?;
+        let dev_only = original.replace("gamma = { path = \"../../14a/gamma\" }\n", "");
+        std::fs::write(&path, &dev_only)?;
+        std::fs::write(&merged, &dev_only)?;
+        assert!(needs_hack(&path, &merged, targets())?);
+
+        let root = dir.path().join("14/Cargo.toml");
+        let mut config = std::fs::read_to_string(&root)?;
+        config.push_str("\n[workspace.metadata.hackerman]\nno-dev = true\n");
+        std::fs::write(&root, config)?;
+        assert!(!needs_hack(&path, &merged, targets())?);
+        assert_eq!(std::fs::read_to_string(&path)?, dev_only);
+        Ok(())
+    }
+
+    #[test]
+    fn merge_reports_conflicts() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let local = BASE.replace("1.0", "1.1");
+        let remote = BASE.replace("1.0", "1.2");
+        let [base, local, remote] = write_all(&dir, &local, &remote);
+
+        assert_eq!(merge(&base, &local, &remote, &local)?, 1);
+        assert!(std::fs::read_to_string(&local)?.contains("<<<<<<<"));
+        Ok(())
+    }
 }
@@ -1,4 +1,5 @@
-use cargo_metadata::Dependency;
+use anyhow::Context;
+use cargo_metadata::{Dependency, Edition, Metadata};
 use cargo_platform::Cfg;
 
 use crate::{feat_graph::Feature, hack::Collect};
@@ -15,6 +16,33 @@ pub enum DependencyKind {
     Unknown,
 }
 
+impl DependencyKind {
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            DependencyKind::Normal => "normal",
+            DependencyKind::Development => "dev",
+            DependencyKind::Build => "build",
+            DependencyKind::Unknown => "unknown",
+        }
+    }
+}
+
+impl std::str::FromStr for DependencyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(DependencyKind::Normal),
+            "dev" => Ok(DependencyKind::Development),
+            "build" => Ok(DependencyKind::Build),
+            _ => Err(format!(
+                "Unknown dependency kind {s:?}, expected `normal`, `dev` or `build`"
+            )),
+        }
+    }
+}
+
 impl From<cargo_metadata::DependencyKind> for DependencyKind {
     fn from(x: cargo_metadata::DependencyKind) -> Self {
         match x {
@@ -26,6 +54,34 @@ impl From<cargo_metadata::DependencyKind> for DependencyKind {
     }
 }
 
+/// Target triple along with cfg flags rustc sets for it
+///
+/// Each target is checked with its own flags so `cfg(not(unix))` dependencies of a windows
+/// target don't get mixed up with ones for linux.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub triple: String,
+    pub cfgs: Vec<Cfg>,
+}
+
+impl Target {
+    /// Check if dependencies for `platform` are used when building for this target
+    #[must_use]
+    pub fn matches(&self, platform: &cargo_platform::Platform) -> bool {
+        platform.matches(&self.triple, &self.cfgs)
+    }
+}
+
+/// Target without any cfg flags, only `[target.'<triple>'.dependencies]` match it
+impl From<&str> for Target {
+    fn from(triple: &str) -> Self {
+        Self {
+            triple: triple.to_string(),
+            cfgs: Vec::new(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct DepKindInfo {
     pub kind: DependencyKind,
@@ -43,18 +99,21 @@ impl DepKindInfo {
         target: None,
     };
 
-    fn satisfies(
-        &self,
-        source: Feature,
-        filter: Collect,
-        platforms: &[&str],
-        cfgs: &[Cfg],
-    ) -> bool {
+    pub const BUILD: Self = Self {
+        kind: DependencyKind::Build,
+        target: None,
+    };
+
+    fn satisfies(&self, source: Feature, filter: Collect, targets: &[Target]) -> bool {
         if self.kind == DependencyKind::Development {
             match filter {
-                Collect::AllTargets | Collect::Target | Collect::NoDev | Collect::NormalOnly => {
-                    return false
-                }
+                Collect::AllTargets
+                | Collect::Target
+                | Collect::NoDev
+                | Collect::NormalOnly
+                | Collect::NormalAndMemberDev
+                | Collect::MemberNorm(_)
+                | Collect::MemberBuild(_) => return false,
                 Collect::MemberDev(pid) => {
                     if let Some(this_fid) = source.fid() {
                         {
@@ -74,7 +133,7 @@ impl DepKindInfo {
 
         self.target
             .as_ref()
-            .map_or(true, |p| p.matches(platforms[0], cfgs))
+            .is_none_or(|p| targets.iter().any(|t| t.matches(p)))
     }
 }
 
@@ -87,6 +146,40 @@ impl From<&Dependency> for DepKindInfo {
     }
 }
 
+/// Feature resolver cargo uses for the workspace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolver {
+    /// Features are unified across all the dependency kinds and targets
+    V1,
+    /// Features of dev, build and inactive target dependencies are kept apart
+    V2,
+}
+
+impl Resolver {
+    /// Resolver configured in the workspace root manifest
+    pub fn of_workspace(meta: &Metadata) -> anyhow::Result<Self> {
+        let path = meta.workspace_root.join("Cargo.toml");
+        let manifest = std::fs::read_to_string(&path)
+            .with_context(|| format!("Couldn't read workspace manifest {path}"))?;
+        Self::from_manifest(&manifest, meta.root_package().map(|p| p.edition))
+    }
+
+    /// `resolver` takes priority, otherwise it's implied by the root package edition,
+    /// virtual workspaces default to the first version
+    pub(crate) fn from_manifest(manifest: &str, edition: Option<Edition>) -> anyhow::Result<Self> {
+        let doc = manifest.parse::<toml_edit::Document>()?;
+        let resolver = ["workspace", "package"]
+            .iter()
+            .find_map(|table| doc.get(table)?.get("resolver")?.as_str());
+        Ok(match resolver {
+            Some("1") => Self::V1,
+            Some(_) => Self::V2,
+            None if edition.is_some_and(|e| e >= Edition::E2021) => Self::V2,
+            None => Self::V1,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Link {
     /// if dependency is specified as optional or required
@@ -115,16 +208,38 @@ impl Link {
     pub(crate) fn is_normal(&self) -> bool {
         self.kinds.iter().any(|k| k.kind == DependencyKind::Normal)
     }
+    pub(crate) fn has_kind(&self, kind: DependencyKind) -> bool {
+        self.kinds.iter().any(|k| k.kind == kind)
+    }
 
-    pub(crate) fn satisfies(
-        &self,
-        source: Feature,
-        filter: Collect,
-        platforms: &[&str],
-        cfgs: &[Cfg],
-    ) -> bool {
+    pub(crate) fn satisfies(&self, source: Feature, filter: Collect, targets: &[Target]) -> bool {
         self.kinds
             .iter()
-            .any(|kind| kind.satisfies(source, filter, platforms, cfgs))
+            .any(|kind| kind.satisfies(source, filter, targets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resolver;
+    use cargo_metadata::Edition;
+
+    #[test]
+    fn resolver_is_detected() -> anyhow::Result<()> {
+        let virt = "[workspace]\nmembers = [\"alpha\"]\n";
+        assert_eq!(Resolver::from_manifest(virt, None)?, Resolver::V1);
+        let v2 = "[workspace]\nmembers = [\"alpha\"]\nresolver = \"2\"\n";
+        assert_eq!(Resolver::from_manifest(v2, None)?, Resolver::V2);
+        let package = "[package]\nname = \"alpha\"\nedition = \"2021\"\n";
+        assert_eq!(
+            Resolver::from_manifest(package, Some(Edition::E2021))?,
+            Resolver::V2
+        );
+        let pinned = "[package]\nname = \"alpha\"\nresolver = \"1\"\n";
+        assert_eq!(
+            Resolver::from_manifest(pinned, Some(Edition::E2021))?,
+            Resolver::V1
+        );
+        Ok(())
     }
 }
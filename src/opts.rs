@@ -1,8 +1,13 @@
-use bpaf::{doc::Style, positional, short, Bpaf, Parser};
+use crate::metadata::DependencyKind;
+use anyhow::Context;
+use bpaf::{construct, doc::Style, positional, short, Bpaf, Parser};
 use cargo_metadata::Metadata;
 use semver::Version;
-use std::{path::PathBuf, str::FromStr};
-use tracing::Level;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tracing::{warn, Level};
 
 const DETAILED_HELP: &[(&str, Style)] = &[
     ("You can pass ", Style::Text),
@@ -28,6 +33,11 @@ pub enum Action {
     ///
     /// Once dependencies are hacked you should restore them before making any
     /// changes.
+    ///
+    /// Settings in `[workspace.metadata.hackerman]` take priority over command line flags.
+    /// `no-dev`, `no-default`, `skip-proc-macro` and `direct-only` can also be set in
+    /// `[package.metadata.hackerman]` of a member, those apply to that member only and take
+    /// priority over both.
     Hack {
         #[bpaf(external(profile))]
         profile: Profile,
@@ -35,6 +45,15 @@ pub enum Action {
         /// Don't perform action, only display it
         dry: bool,
 
+        /// With `--dry` list members and their changes adding the most features first
+        ///
+        /// By default both members and changes are sorted by name.
+        sort_by_impact: bool,
+
+        /// Don't report progress on stderr
+        #[bpaf(short, long)]
+        quiet: bool,
+
         /// Include dependencies checksum into stash
         ///
         /// This helps to ensure you can go back to original (unhacked) dependencies: to be able to
@@ -55,6 +74,85 @@ pub enum Action {
         /// Don't unify dev dependencies
         #[bpaf(short('D'), long)]
         no_dev: bool,
+
+        /// Don't enable default features of dependencies for members that opt out of them
+        ///
+        /// By default `default` is unified as any other feature. With this option members
+        /// using a dependency with `default-features = false` keep it that way, features enabled
+        /// only by `default` are not added either. Can be made the default behavior with
+        /// `no-default = true` in `[workspace.metadata.hackerman]`.
+        no_default: bool,
+
+        /// How to name dependencies that need renaming: `hash`, `version` or `source`
+        ///
+        /// Renames are needed when a member uses several copies of the same crate. `hash` gives
+        /// `hackerman-name-<hash>`, `version` gives `name-1`, `name-0-7` and so on, `source` gives
+        /// `name-crates`, `name-git`, `name-registry` or `name-path`. Can be set with
+        /// `rename = "version"` in `[workspace.metadata.hackerman]`.
+        #[bpaf(argument("STRATEGY"), fallback(RenameStrategy::Hash))]
+        rename: RenameStrategy,
+
+        /// Don't unify features of proc-macro crates
+        ///
+        /// Proc macros are compiled for the host and unifying them rarely saves anything.
+        /// Can be made the default behavior with `skip-proc-macro = true` in
+        /// `[workspace.metadata.hackerman]`.
+        skip_proc_macro: bool,
+
+        /// Only unify dependencies members declare themselves
+        ///
+        /// Gives smaller changes at the cost of less complete unification: features of
+        /// dependencies that come through other crates can still differ. Can be made the
+        /// default behavior with `direct-only = true` in `[workspace.metadata.hackerman]`.
+        direct_only: bool,
+
+        /// Unify through `[workspace.dependencies]` of the workspace root
+        ///
+        /// Features all the members need are added to workspace dependencies and members switch
+        /// to `workspace = true`, listing the remaining features. Renamed dependencies stay in
+        /// the members. Can be made the default behavior with `workspace-deps = true` in
+        /// `[workspace.metadata.hackerman]`.
+        workspace_deps: bool,
+
+        /// Keep updating the rest of the members if one of them fails
+        ///
+        /// Without it `hack` leaves all the manifests unchanged if any of them can't be
+        /// updated. With it all the failures are reported at the end and the report lists
+        /// members that were hacked, use `cargo hackerman restore` to undo the partial changes.
+        keep_going: bool,
+
+        /// Hack every workspace found under `--manifest-path` directory
+        ///
+        /// Directories are searched recursively for `Cargo.toml` files with a `[workspace]`
+        /// table, each workspace is hacked on its own, skipping `target` and hidden directories.
+        all_workspaces: bool,
+
+        /// Write a json record of all the changed manifests and dependencies to this file
+        ///
+        /// Can't be used with `--patch`, nothing is changed in that case.
+        #[bpaf(argument("PATH"))]
+        report: Option<PathBuf>,
+
+        /// Write changes as a unified diff to this file instead of changing the manifests
+        ///
+        /// Paths are relative to the workspace root, the patch can be applied with `git apply`
+        /// from there.
+        #[bpaf(argument("PATH"))]
+        patch: Option<PathBuf>,
+
+        /// Don't add the banner comment to hacked manifests
+        ///
+        /// Original dependencies are still stashed, without the banner `restore` relies on the
+        /// stash only. Same as `banner = false` in `[workspace.metadata.hackerman]`.
+        no_banner: bool,
+
+        /// Unify features for this feature selection instead of the default one
+        ///
+        /// Flags are passed to `cargo metadata` and selected features of the members are
+        /// unified along with the default ones, helps when the workspace is usually built with
+        /// `--all-features`.
+        #[bpaf(external(feature_selection))]
+        selection: FeatureSelection,
     },
 
     /// Remove crate dependency unification added by the `hack` command
@@ -63,7 +161,21 @@ pub enum Action {
         #[bpaf(external(profile))]
         profile: Profile,
 
+        /// Don't perform action, only list manifests that would be restored
+        dry: bool,
+
+        /// Replay the stash even if parts of it are corrupted
+        ///
+        /// Without it a manifest with a corrupted stash entry or banner marker is refused, with
+        /// it such entries are skipped with a warning and the rest of the stash is restored.
+        /// Edits made to hacked dependencies while hacked are lost either way.
+        #[bpaf(long, long("ignore-checksum"))]
+        force: bool,
+
         /// Restore individual files instead of the whole workspace
+        ///
+        /// Directories are searched for `Cargo.toml` files recursively, glob patterns such as
+        /// `crates/*/Cargo.toml` are expanded
         #[bpaf(positional("TOML"))]
         separate: Vec<PathBuf>,
     },
@@ -72,6 +184,12 @@ pub enum Action {
     ///
     /// Similar to `cargo-hackerman hack --dry`, but also sets exit status to 1
     /// so you can use it as part of CI process
+    ///
+    /// Every dependency that needs changes lists features the member is missing, those are
+    /// the ones `hack` would add
+    ///
+    /// Features members ask for that their dependencies no longer have are reported separately,
+    /// this happens when lock file updates pick a version that dropped a feature hackerman added
     #[bpaf(command)]
     Check {
         #[bpaf(external(profile))]
@@ -80,6 +198,16 @@ pub enum Action {
         /// Don't unify dev dependencies
         #[bpaf(short('D'), long)]
         no_dev: bool,
+
+        /// Don't enable default features of dependencies for members that opt out of them
+        no_default: bool,
+
+        /// Print the result for every member as JSON
+        json: bool,
+
+        /// Check unification for this feature selection, same as in `hack`
+        #[bpaf(external(feature_selection))]
+        selection: FeatureSelection,
     },
 
     /// Restore files and merge with the default merge driver
@@ -100,8 +228,13 @@ pub enum Action {
     /// ```text
     /// Cargo.toml merge=hackerman
     /// ```
+    ///
+    /// With `--check` hackerman also checks if features in the workspace are still unified
+    /// once the merged file is in place and prints a note to stderr if they are not.
     #[bpaf(command("merge"))]
     MergeDriver {
+        /// Check if the workspace needs to be hacked again after a clean merge
+        check: bool,
         #[bpaf(positional("BASE"))]
         base: PathBuf,
         #[bpaf(positional("LOCAL"))]
@@ -125,20 +258,28 @@ pub enum Action {
     ///
     /// `explain` starts at a given crate/feature and follows reverse dependency links until it
     /// reaches all the crossing points with the workspace but without entering the workspace
-    /// itself.
+    /// itself. With `--until` it stops at the given crate instead and only shows how that
+    /// crate depends on the target.
     ///
-    /// White nodes represent workspace members, round nodes represent features, octagonal nodes
-    /// represent base crates. Dotted line represents dev-only dependency, dashed line - both
-    /// dev and normal but with different features across them. Target is usually highlighted.
+    /// White nodes inside of the workspace box represent workspace members, round nodes
+    /// represent features, octagonal nodes represent base crates. Dotted line represents
+    /// dev-only dependency, dashed line - both dev and normal but with different features
+    /// across them. Target is usually highlighted.
     /// By default hackerman expands packages info feature nodes which can be reverted with
     /// `-P` and tries to reduce transitive dependencies to keep the tree more readable -
-    /// this can be reverted with `-T`.
+    /// this can be reverted with `-T`. `--features-only` keeps one node per package but lists
+    /// enabled features of the package on it.
     ///
     /// If a crate is present in several versions you can specify version of the one you
-    /// are interested in but it's optional.
+    /// are interested in but it's optional. Version can also be given cargo style, as
+    /// `crate@version`.
     ///
     /// You can also specify which feature to look for, otherwise hackerman will be
     /// looking for all of them.
+    ///
+    /// Fonts and spacing of the dot output can be set for the whole workspace with
+    /// `fontname`, `fontsize`, `nodesep`, `ranksep` and similar keys in
+    /// `[workspace.metadata.hackerman.graph]`.
     Explain {
         #[bpaf(external(profile))]
         profile: Profile,
@@ -151,23 +292,167 @@ pub enum Action {
         #[bpaf(short('P'), long)]
         package_nodes: bool,
 
+        /// Use package nodes labeled with enabled features of the crate
+        features_only: bool,
+
         /// Print dot file to stdout instead of spawning `xdot`
         #[bpaf(short, long)]
         stdout: bool,
 
+        /// Only follow `normal`, `dev` or `build` dependencies
+        ///
+        /// With `normal` every link in the chain must be a normal dependency, with `dev` or
+        /// `build` the link from a workspace member must be of this kind.
+        #[bpaf(argument("KIND"))]
+        kind: Option<DependencyKind>,
+
+        /// Don't follow dev-only dependencies
+        #[bpaf(short('D'), long)]
+        no_dev: bool,
+
+        /// Stop at this crate instead of the workspace, only paths from it are shown
+        #[bpaf(argument("CRATE"))]
+        until: Option<String>,
+
+        /// Output format: `dot`, `json` or `text`, json and text are always printed to stdout
+        #[bpaf(argument("FORMAT"), fallback(Format::Dot))]
+        format: Format,
+
+        #[bpaf(external(layout))]
+        layout: Layout,
+
         #[bpaf(positional("CRATE"))]
         krate: String,
-        #[bpaf(external(feature_if))]
-        feature: Option<String>,
-        #[bpaf(external(version_if))]
+        #[bpaf(external(feature_and_version))]
+        feature_version: (Option<String>, Option<Version>),
+    },
+
+    /// Lists workspace members that depend on a crate
+    ///
+    ///
+    ///
+    /// For every workspace member that needs the crate directly or indirectly prints the
+    /// shortest chain of features leading from the member to the crate, one member per line.
+    /// This is a lightweight text version of `explain`.
+    #[bpaf(command)]
+    Dependents {
+        #[bpaf(external(profile))]
+        profile: Profile,
+
+        #[bpaf(positional("CRATE"))]
+        krate: String,
+        #[bpaf(external(feature_and_version))]
+        feature_version: (Option<String>, Option<Version>),
+    },
+
+    /// Explains why a feature of a crate is enabled
+    ///
+    ///
+    ///
+    /// For every workspace member that enables the feature directly or indirectly prints the
+    /// shortest chain of feature activations leading from the member to the feature, one member
+    /// per line. Unlike `dependents` chains also go through weak `dep?/feature` dependencies,
+    /// such steps are only taken if the optional dependency is enabled by something else.
+    ///
+    /// Examples:
+    ///
+    /// ```sh
+    /// cargo hackerman why-feature serde std
+    /// cargo hackerman why-feature syn@1.0.109 full
+    /// ```
+    #[bpaf(command("why-feature"))]
+    WhyFeature {
+        #[bpaf(external(profile))]
+        profile: Profile,
+
+        #[bpaf(positional("CRATE"))]
+        krate: String,
+        #[bpaf(positional("FEATURE"))]
+        feature: String,
+        #[bpaf(positional("VERSION"))]
         version: Option<Version>,
     },
 
+    /// Compare features declared by two versions of a crate
+    ///
+    ///
+    ///
+    /// Both versions must be present in the resolved dependency graph. Prints features added
+    /// in the new version, removed from the old one and features with changed contents.
+    ///
+    /// Examples:
+    ///
+    /// ```sh
+    /// cargo hackerman diff-features syn 1.0.109 2.0.48
+    /// ```
+    #[bpaf(command("diff-features"))]
+    DiffFeatures {
+        #[bpaf(external(profile))]
+        profile: Profile,
+
+        #[bpaf(positional("CRATE"))]
+        krate: String,
+        #[bpaf(positional("OLD"))]
+        old: Version,
+        #[bpaf(positional("NEW"))]
+        new: Version,
+    },
+
     /// Lists all the duplicates in the workspace
+    ///
+    ///
+    ///
+    /// Copies of a crate that set the same `links` key are marked as ones that will not
+    /// compile, cargo allows only one package to link a given native library.
     #[bpaf(command)]
     Dupes {
         #[bpaf(external(profile))]
         profile: Profile,
+
+        /// Ignore duplicates only reachable through dev or build dependencies
+        normal_only: bool,
+
+        /// Mark semver compatible versions, `cargo update` might be able to deduplicate them
+        semver: bool,
+    },
+
+    /// Lists dependency cycles in the feature graph
+    ///
+    ///
+    ///
+    /// Cycles are legal in cargo, for example a crate can depend on itself with extra features
+    /// as a dev dependency, but `explain` and `tree` can't remove redundant links inside of them.
+    #[bpaf(command)]
+    Cycles {
+        #[bpaf(external(profile))]
+        profile: Profile,
+    },
+
+    /// Rank enabled features by how many extra crates they bring in
+    ///
+    ///
+    ///
+    /// A crate counts towards a feature if disabling that feature alone would drop the
+    /// crate from the build on the current target. Features that add nothing are not listed.
+    #[bpaf(command("bloat-features"))]
+    BloatFeatures {
+        #[bpaf(external(profile))]
+        profile: Profile,
+
+        /// List crates each feature brings in
+        crates: bool,
+    },
+
+    /// Print crate, feature and duplicate counts along with the deepest dependency chain
+    ///
+    ///
+    ///
+    /// Counts are for the current target, same as in `dupes`. The deepest chain follows
+    /// normal and build dependencies starting from workspace members.
+    #[bpaf(command("graph-stats"))]
+    GraphStats {
+        #[bpaf(external(profile))]
+        profile: Profile,
     },
 
     #[bpaf(command)]
@@ -180,6 +465,7 @@ pub enum Action {
     ///
     /// ```sh
     /// cargo hackerman tree rand 0.8.4
+    /// cargo hackerman tree rand@0.8.4
     /// cargo hackerman tree serde_json preserve_order
     /// ```
     Tree {
@@ -202,20 +488,41 @@ pub enum Action {
         #[bpaf(short, long)]
         workspace: bool,
 
+        /// Only show what is pulled in by optional dependencies
+        optional_only: bool,
+
+        /// Only show paths leading to crates used in several versions
+        ///
+        /// With CRATE only paths to copies of that crate are shown
+        duplicates_only: bool,
+
+        #[bpaf(external(feature_selection))]
+        selection: FeatureSelection,
+
         /// Print dot file to stdout instead of spawning `xdot`
         #[bpaf(short, long)]
         stdout: bool,
 
+        /// Output format: `dot`, `json` or `text`, json and text are always printed to stdout
+        #[bpaf(argument("FORMAT"), fallback(Format::Dot))]
+        format: Format,
+
+        #[bpaf(external(layout))]
+        layout: Layout,
+
         #[bpaf(positional("CRATE"))]
         krate: Option<String>,
-        #[bpaf(external(feature_if))]
-        feature: Option<String>,
-        #[bpaf(external(version_if))]
-        version: Option<Version>,
+        #[bpaf(external(feature_and_version))]
+        feature_version: (Option<String>, Option<Version>),
     },
 
     #[bpaf(command("show"))]
-    /// Show crate manifest, readme, repository or documentation
+    /// Show crate manifest, readme, repository, documentation, license or versions in use
+    ///
+    ///
+    ///
+    /// Documentation and repository can be opened for crates the workspace doesn't use,
+    /// docs.rs and crates.io pages are opened for them instead.
     ///
     ///
     ///
@@ -237,18 +544,134 @@ pub enum Action {
     },
 }
 
-fn feature_if() -> impl Parser<Option<String>> {
-    positional::<String>("FEATURE")
-        .parse::<_, _, &'static str>(|s| match Version::from_str(&s) {
-            Err(_) => Ok(s),
-            Ok(_) => Err("not a feature"),
+fn version_if() -> impl Parser<Option<Version>> {
+    positional::<Version>("VERSION").optional().catch()
+}
+
+/// Crate name and version, crate can be given with cargo's `name@version` syntax instead of
+/// a separate version but not both
+pub fn package_id_spec(
+    krate: &str,
+    version: Option<Version>,
+) -> anyhow::Result<(String, Option<Version>)> {
+    let Some((name, spec)) = krate.split_once('@') else {
+        return Ok((krate.to_string(), version));
+    };
+    if version.is_some() {
+        anyhow::bail!("Version of {name} is given twice: in {krate} and as a separate argument");
+    }
+    let spec = Version::from_str(spec)
+        .with_context(|| format!("Expected a full version in {krate}, for example {name}@1.0.0"))?;
+    Ok((name.to_string(), Some(spec)))
+}
+
+/// Optional feature and version of a crate, in any order: anything that parses as a version is
+/// a version
+fn feature_and_version() -> impl Parser<(Option<String>, Option<Version>)> {
+    let first = positional::<String>("FEATURE").optional();
+    let second = positional::<String>("VERSION").optional();
+    construct!(first, second).parse(|(first, second)| {
+        let classify = |s: String| match Version::from_str(&s) {
+            Ok(version) => (None, Some(version)),
+            Err(_) => (Some(s), None),
+        };
+        Ok::<_, String>(match (first.map(classify), second.map(classify)) {
+            (None, _) => (None, None),
+            (Some(item), None) => item,
+            (Some((Some(feature), None)), Some((None, Some(version))))
+            | (Some((None, Some(version))), Some((Some(feature), None))) => {
+                (Some(feature), Some(version))
+            }
+            (Some((Some(a), _)), Some((Some(b), _))) => {
+                return Err(format!(
+                    "Expected a feature and a version, got two features: {a} and {b}"
+                ))
+            }
+            _ => return Err("Expected a feature and a version, got two versions".to_string()),
         })
-        .optional()
-        .catch()
+    })
 }
 
-fn version_if() -> impl Parser<Option<Version>> {
-    positional::<Version>("VERSION").optional().catch()
+#[derive(Debug, Clone, Bpaf)]
+/// Graph layout:
+pub struct Layout {
+    /// Direction of the graph layout: `TB` (default), `LR`, `BT` or `RL`
+    #[bpaf(argument("DIR"))]
+    pub rankdir: Option<RankDir>,
+    /// How to draw edges, for example `ortho`, `polyline` or `curved`
+    #[bpaf(argument("STYLE"))]
+    pub splines: Option<String>,
+}
+
+impl Layout {
+    /// Graph attributes for dot output
+    #[must_use]
+    pub fn graph_attrs(&self) -> Vec<(String, String)> {
+        let mut res = Vec::new();
+        if let Some(rankdir) = self.rankdir {
+            res.push(("rankdir".to_string(), format!("{rankdir:?}")));
+        }
+        if let Some(splines) = &self.splines {
+            res.push(("splines".to_string(), splines.clone()));
+        }
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Graphviz `rankdir` values
+pub enum RankDir {
+    TB,
+    LR,
+    BT,
+    RL,
+}
+
+impl FromStr for RankDir {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "TB" => Ok(RankDir::TB),
+            "LR" => Ok(RankDir::LR),
+            "BT" => Ok(RankDir::BT),
+            "RL" => Ok(RankDir::RL),
+            _ => Err(format!(
+                "Unknown direction {s:?}, expected `TB`, `LR`, `BT` or `RL`"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Bpaf)]
+/// Feature selection for workspace members, same as in cargo:
+pub struct FeatureSelection {
+    /// Space or comma separated list of features to activate, `member/feature` for a single member
+    #[bpaf(short('F'), long("features"), argument("FEATURES"))]
+    pub features: Vec<String>,
+    /// Activate all available features
+    pub all_features: bool,
+    /// Do not activate the `default` feature
+    pub no_default_features: bool,
+}
+
+impl FeatureSelection {
+    /// The same selection as `cargo` command line flags
+    #[must_use]
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        for features in &self.features {
+            res.push(String::from("--features"));
+            res.push(features.clone());
+        }
+        if self.all_features {
+            res.push(String::from("--all-features"));
+        }
+        if self.no_default_features {
+            res.push(String::from("--no-default-features"));
+        }
+        res
+    }
 }
 
 #[derive(Debug, Clone, Bpaf)]
@@ -256,24 +679,67 @@ fn version_if() -> impl Parser<Option<Version>> {
 #[bpaf(custom_usage(&[("CARGO_OPTS", Style::Metavar)]))]
 pub struct Profile {
     #[bpaf(argument("PATH"), fallback("Cargo.toml".into()))]
-    /// Path to Cargo.toml file
+    /// Path to Cargo.toml file or a directory containing it
     pub manifest_path: PathBuf,
 
     /// Require Cargo.lock and cache are up to date
     pub frozen: bool,
     /// Require Cargo.lock is up to date
+    ///
+    /// `hack` restores the manifests and fails if unified dependencies change Cargo.lock
     pub locked: bool,
     /// Run without accessing the network
     pub offline: bool,
+    /// Reuse `cargo metadata` output from a previous run if manifests, cargo config and lock file
+    /// are unchanged, cache is kept in the target directory
+    pub cache: bool,
+
+    /// Read `cargo metadata` output from a file or from stdin with `-` instead of running cargo
+    ///
+    /// Feature flags such as `--features` can't be used with it, pass them to `cargo metadata`
+    /// instead. `Cargo.lock` is not regenerated after the manifests change.
+    #[bpaf(argument("FILE"))]
+    pub metadata_from: Option<PathBuf>,
+
+    /// Override a cargo configuration value, passed to `cargo metadata` as is
+    ///
+    /// Same as cargo's own `--config`: either `KEY=VALUE` in TOML syntax or a path to an extra
+    /// configuration file. Requires cargo 1.63 or newer. Can be repeated.
+    #[bpaf(argument("KEY=VALUE"))]
+    pub config: Vec<String>,
+
+    /// Target triple to use instead of `CARGO_BUILD_TARGET`, `build.target` from cargo config
+    /// or the host one
+    ///
+    /// Aliases `wasm`, `wasi`, `linux`, `windows`, `macos` and `unix` stand for common
+    /// triples, `unix` checks both linux and macos.
+    #[bpaf(argument("TRIPLE"))]
+    pub target: Vec<String>,
+
+    /// Pretend cfg such as `target_os="windows"` is not set, to see what depends on it
+    #[bpaf(argument("CFG"))]
+    pub exclude_cfg: Vec<String>,
+
+    /// Highlight crate names and versions: `auto`, `always` or `never`
+    #[bpaf(argument("WHEN"), fallback(ColorChoice::Auto))]
+    pub color: ColorChoice,
 
     #[bpaf(external)]
     pub verbosity: (usize, Level),
 }
 
 impl Profile {
-    pub fn exec(&self) -> anyhow::Result<Metadata> {
-        let mut cmd = cargo_metadata::MetadataCommand::new();
+    /// Target triples to check features for
+    pub fn targets(&self) -> anyhow::Result<Vec<String>> {
+        if self.target.is_empty() {
+            crate::config::default_targets(&std::env::current_dir()?)
+        } else {
+            Ok(crate::config::expand_target_aliases(&self.target))
+        }
+    }
 
+    /// Options passed to `cargo metadata`, without verbosity flags
+    fn options(&self) -> Vec<String> {
         let mut extra = Vec::new();
         if self.frozen {
             extra.push(String::from("--frozen"));
@@ -284,13 +750,134 @@ impl Profile {
         if self.offline {
             extra.push(String::from("--offline"));
         }
+        for config in &self.config {
+            extra.push(String::from("--config"));
+            extra.push(config.clone());
+        }
+        extra
+    }
+
+    /// Path to the manifest file, `--manifest-path` can also point to a directory containing it
+    fn manifest(&self) -> anyhow::Result<PathBuf> {
+        if !self.manifest_path.is_dir() {
+            return Ok(self.manifest_path.clone());
+        }
+        let manifest = self.manifest_path.join("Cargo.toml");
+        if !manifest.is_file() {
+            anyhow::bail!(
+                "{} is a directory without Cargo.toml in it",
+                self.manifest_path.display()
+            );
+        }
+        Ok(manifest)
+    }
+
+    fn run(&self, manifest: &Path, mut extra: Vec<String>) -> anyhow::Result<Metadata> {
+        let mut cmd = cargo_metadata::MetadataCommand::new();
         for _ in 0..self.verbosity.0 {
             extra.push(String::from("-v"));
         }
-        cmd.manifest_path(&self.manifest_path);
+        cmd.manifest_path(manifest);
         cmd.other_options(extra);
+        // cargo's own diagnostic is the most useful part of the error
+        cmd.exec()
+            .map_err(|err| match err {
+                cargo_metadata::Error::CargoMetadata { stderr } => {
+                    anyhow::anyhow!("{}", stderr.trim())
+                }
+                err => anyhow::Error::from(err),
+            })
+            .with_context(|| format!("cargo metadata failed for {}", manifest.display()))
+    }
+
+    pub fn exec(&self) -> anyhow::Result<Metadata> {
+        self.exec_with(&FeatureSelection::default())
+    }
+
+    /// Same as [`exec`](Self::exec), but cargo resolves dependencies for `selection`
+    pub fn exec_with(&self, selection: &FeatureSelection) -> anyhow::Result<Metadata> {
+        if let Some(path) = &self.metadata_from {
+            if !selection.cargo_args().is_empty() {
+                anyhow::bail!(
+                    "Feature flags can't be used with --metadata-from, \
+                    pass them to `cargo metadata` that produced {}",
+                    path.display()
+                );
+            }
+            return read_metadata(path);
+        }
+        let manifest = self.manifest()?;
+        let mut key = self.options();
+        key.extend(selection.cargo_args());
+        if self.cache {
+            if let Some(meta) = crate::cache::load(&manifest, &key) {
+                return Ok(meta);
+            }
+        }
+        let meta = self.run(&manifest, key.clone())?;
+        if self.cache {
+            crate::cache::store(&manifest, &key, &meta)?;
+        }
+        if let Some(root) = workspace_root_of_member(&manifest, &meta) {
+            warn!(
+                "{} is a member of the workspace at {}, working with the whole workspace",
+                manifest.display(),
+                root.display()
+            );
+        }
+        Ok(meta)
+    }
+
+    /// Regenerate `Cargo.lock` after manifests were changed
+    ///
+    /// Never uses cached metadata. `--frozen`, `--locked` and `--offline` are passed to cargo
+    /// as is so it fails instead of updating the lock file or accessing the network. Skipped
+    /// with `--metadata-from`.
+    pub fn regenerate_lock(&self) -> anyhow::Result<()> {
+        if self.metadata_from.is_some() {
+            warn!("Metadata comes from --metadata-from, Cargo.lock is not regenerated");
+            return Ok(());
+        }
+        let manifest = self.manifest()?;
+        let options = self.options();
+        let restricted = self.frozen || self.locked || self.offline;
+        let meta = self.run(&manifest, options.clone()).with_context(|| {
+            if restricted {
+                format!("Failed to regenerate Cargo.lock with {}", options.join(" "))
+            } else {
+                "Failed to regenerate Cargo.lock".to_string()
+            }
+        })?;
+        if self.cache {
+            crate::cache::store(&manifest, &options, &meta)?;
+        }
+        Ok(())
+    }
+}
 
-        Ok(cmd.exec()?)
+/// Parse `cargo metadata` output saved in `path`, `-` stands for stdin
+fn read_metadata(path: &Path) -> anyhow::Result<Metadata> {
+    let data = if path == Path::new("-") {
+        std::io::read_to_string(std::io::stdin()).context("Failed to read metadata from stdin")?
+    } else {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read metadata from {}", path.display()))?
+    };
+    cargo_metadata::MetadataCommand::parse(data)
+        .with_context(|| format!("{} doesn't contain valid cargo metadata", path.display()))
+}
+
+/// Workspace root manifest if `manifest_path` is not the root manifest itself but one of
+/// the members
+///
+/// `cargo metadata` always describes the whole workspace, even for a member manifest
+fn workspace_root_of_member(manifest_path: &Path, meta: &Metadata) -> Option<PathBuf> {
+    let root = meta.workspace_root.join("Cargo.toml").into_std_path_buf();
+    let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if canonical(manifest_path) == canonical(&root) {
+        None
+    } else {
+        Some(root)
     }
 }
 
@@ -300,6 +887,10 @@ pub enum Focus {
     /// Show crate manifest
     Manifest,
 
+    #[bpaf(long)]
+    /// Show manifest of a hacked workspace member as it was before `hack`
+    Stash,
+
     #[bpaf(short, long)]
     /// Show crate readme
     Readme,
@@ -311,6 +902,91 @@ pub enum Focus {
     #[bpaf(short('R'), long, long("repo"), long("git"))]
     /// Repository
     Repository,
+
+    #[bpaf(long)]
+    /// List all versions of a crate in use along with their sources
+    Versions,
+
+    #[bpaf(short('L'), long, long("license"))]
+    /// Show crate license and license file
+    Licenses,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Output format for graph commands
+pub enum Format {
+    /// Graphviz dot file
+    Dot,
+    /// Nodes and edges as a json document
+    Json,
+    /// Plain text, one node per line followed by the nodes it links to
+    Text,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(Format::Dot),
+            "json" => Ok(Format::Json),
+            "text" => Ok(Format::Text),
+            _ => Err(format!(
+                "Unknown format {s:?}, expected `dot`, `json` or `text`"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// When to highlight crate names and versions
+pub enum ColorChoice {
+    /// When printing to a terminal and `NO_COLOR` is not set
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "Unknown color choice {s:?}, expected `auto`, `always` or `never`"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Naming scheme for renamed dependencies in hacked manifests
+pub enum RenameStrategy {
+    /// `hackerman-name-<hash of source and version>`
+    #[default]
+    Hash,
+    /// `name-<semver compatible part of the version>`
+    Version,
+    /// `name-<kind of the source>`
+    Source,
+}
+
+impl FromStr for RenameStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hash" => Ok(RenameStrategy::Hash),
+            "version" => Ok(RenameStrategy::Version),
+            "source" => Ok(RenameStrategy::Source),
+            _ => Err(format!(
+                "Unknown rename strategy {s:?}, expected `hash`, `version` or `source`"
+            )),
+        }
+    }
 }
 
 fn verbosity() -> impl Parser<(usize, Level)> {
@@ -332,6 +1008,242 @@ fn verbosity() -> impl Parser<(usize, Level)> {
         })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{
+        package_id_spec, read_metadata, workspace_root_of_member, Action, ColorChoice,
+        FeatureSelection, Profile,
+    };
+    use crate::feat_graph::test::copy_demo_workspace;
+    use semver::Version;
+    use std::path::Path;
+    use tracing::Level;
+
+    #[test]
+    fn feature_and_version_in_any_order() {
+        let parse = |args: &[&str]| match super::action().run_inner(args) {
+            Ok(Action::Tree {
+                krate,
+                feature_version,
+                ..
+            }) => Ok((krate, feature_version)),
+            Ok(other) => panic!("unexpected {other:?}"),
+            Err(err) => Err(err),
+        };
+        let expected = (
+            Some("serde".to_string()),
+            (Some("derive".to_string()), Some(Version::new(1, 0, 160))),
+        );
+        assert_eq!(
+            parse(&["tree", "serde", "derive", "1.0.160"]).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse(&["tree", "serde", "1.0.160", "derive"]).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse(&["tree", "serde", "1.0.160"]).unwrap().1,
+            (None, Some(Version::new(1, 0, 160)))
+        );
+        assert_eq!(
+            parse(&["tree", "serde", "derive"]).unwrap().1,
+            (Some("derive".to_string()), None)
+        );
+        assert!(parse(&["tree", "serde", "derive", "std"]).is_err());
+    }
+
+    #[test]
+    fn features_are_selected_like_in_cargo() {
+        let parse = |args: &[&str]| match super::action().run_inner(args) {
+            Ok(Action::Tree { selection, .. }) => selection.features,
+            Ok(other) => panic!("unexpected {other:?}"),
+            Err(err) => panic!("{err:?}"),
+        };
+        assert_eq!(parse(&["tree", "--features", "a,b"]), ["a,b"]);
+        assert_eq!(parse(&["tree", "-F", "a", "--features", "b"]), ["a", "b"]);
+    }
+
+    #[test]
+    fn package_id_spec_is_split() -> anyhow::Result<()> {
+        let v = Version::new(0, 8, 4);
+        assert_eq!(package_id_spec("rand", None)?, ("rand".to_string(), None));
+        assert_eq!(
+            package_id_spec("rand", Some(v.clone()))?,
+            ("rand".to_string(), Some(v.clone()))
+        );
+        assert_eq!(
+            package_id_spec("rand@0.8.4", None)?,
+            ("rand".to_string(), Some(v.clone()))
+        );
+        assert!(package_id_spec("rand@0.8.4", Some(v)).is_err());
+        assert!(package_id_spec("rand@0.8", None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn member_manifest_is_detected() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["12", "8a"])?;
+        let root = dir.path().join("12/Cargo.toml");
+        let member = dir.path().join("12/alpha/Cargo.toml");
+        let meta = cargo_metadata::MetadataCommand::new()
+            .manifest_path(&member)
+            .other_options(vec!["--offline".to_string()])
+            .exec()?;
+
+        assert_eq!(
+            workspace_root_of_member(&member, &meta).map(|p| p.canonicalize().unwrap()),
+            Some(root.canonicalize()?)
+        );
+        assert_eq!(workspace_root_of_member(&root, &meta), None);
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_path_can_be_a_directory() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["12", "8a"])?;
+        let mut profile = Profile {
+            manifest_path: dir.path().join("12"),
+            frozen: false,
+            locked: false,
+            offline: true,
+            cache: false,
+            metadata_from: None,
+            config: Vec::new(),
+            target: Vec::new(),
+            exclude_cfg: Vec::new(),
+            color: ColorChoice::Auto,
+            verbosity: (0, Level::WARN),
+        };
+        assert_eq!(profile.manifest()?, dir.path().join("12/Cargo.toml"));
+        assert_eq!(profile.exec()?.workspace_members.len(), 2);
+
+        profile.manifest_path = dir.path().to_path_buf();
+        let err = profile.exec().unwrap_err();
+        assert!(err.to_string().contains("without Cargo.toml"), "{err:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_errors_include_cargo_output() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let manifest_path = dir.path().join("Cargo.toml");
+        std::fs::write(&manifest_path, "[package]\nname = \"alpha\"\nversion = 1\n")?;
+        let profile = Profile {
+            manifest_path: manifest_path.clone(),
+            frozen: false,
+            locked: false,
+            offline: true,
+            cache: false,
+            metadata_from: None,
+            config: Vec::new(),
+            target: Vec::new(),
+            exclude_cfg: Vec::new(),
+            color: ColorChoice::Auto,
+            verbosity: (0, Level::WARN),
+        };
+        let err = profile.exec().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("cargo metadata failed for {}", manifest_path.display())
+        );
+        let cause = err.root_cause().to_string();
+        assert!(cause.starts_with("error: "), "{cause}");
+        assert!(cause.contains("version"), "{cause}");
+        Ok(())
+    }
+
+    #[test]
+    fn config_overrides_are_passed_to_cargo() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["12", "8a"])?;
+        let mut profile = Profile {
+            manifest_path: dir.path().join("12/Cargo.toml"),
+            frozen: false,
+            locked: false,
+            offline: false,
+            cache: false,
+            metadata_from: None,
+            config: vec!["net.offline=true".to_string()],
+            target: Vec::new(),
+            exclude_cfg: Vec::new(),
+            color: ColorChoice::Auto,
+            verbosity: (0, Level::WARN),
+        };
+        assert_eq!(profile.options(), ["--config", "net.offline=true"]);
+        assert_eq!(profile.exec()?.workspace_members.len(), 2);
+
+        profile.config.push("not a toml key".to_string());
+        let err = profile.exec().unwrap_err();
+        assert!(err.root_cause().to_string().contains("--config"), "{err:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_is_read_from_file() -> anyhow::Result<()> {
+        let profile = Profile {
+            manifest_path: "does/not/exist".into(),
+            frozen: false,
+            locked: false,
+            offline: true,
+            cache: false,
+            metadata_from: Some("test_workspaces/15/metadata.json".into()),
+            config: Vec::new(),
+            target: Vec::new(),
+            exclude_cfg: Vec::new(),
+            color: ColorChoice::Auto,
+            verbosity: (0, Level::WARN),
+        };
+        assert_eq!(profile.exec()?.workspace_members.len(), 3);
+        // nothing to regenerate the lock file for
+        profile.regenerate_lock()?;
+
+        let selection = FeatureSelection {
+            all_features: true,
+            ..FeatureSelection::default()
+        };
+        let err = profile.exec_with(&selection).unwrap_err();
+        assert!(err.to_string().contains("--metadata-from"), "{err:?}");
+
+        let err = read_metadata(Path::new("Cargo.toml")).unwrap_err();
+        assert!(err.to_string().contains("valid cargo metadata"), "{err:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn frozen_lock_regeneration_fails_on_changes() -> anyhow::Result<()> {
+        let dir = copy_demo_workspace(&["12", "8a"])?;
+
+        let profile = Profile {
+            manifest_path: dir.path().join("12/Cargo.toml"),
+            frozen: true,
+            locked: false,
+            offline: false,
+            cache: false,
+            metadata_from: None,
+            config: Vec::new(),
+            target: Vec::new(),
+            exclude_cfg: Vec::new(),
+            color: ColorChoice::Auto,
+            verbosity: (0, Level::WARN),
+        };
+        profile.regenerate_lock()?;
+
+        let lock_path = dir.path().join("12/Cargo.lock");
+        let lock = std::fs::read_to_string(&lock_path)?;
+        let manifest_path = dir.path().join("12/alpha/Cargo.toml");
+        let manifest = std::fs::read_to_string(&manifest_path)?.replace(
+            "[dependencies]\n",
+            "[dependencies]\nbeta = { path = \"../beta\" }\n",
+        );
+        std::fs::write(&manifest_path, manifest)?;
+
+        let err = profile.regenerate_lock().unwrap_err();
+        assert!(err.to_string().contains("--frozen"), "{err:?}");
+        assert_eq!(lock, std::fs::read_to_string(&lock_path)?);
+        Ok(())
+    }
+}
+
 #[cfg(all(test, unix))]
 mod readme {
 
@@ -342,6 +1254,7 @@ mod readme {
             .write(true)
             .read(true)
             .create(true)
+            .truncate(false)
             .open(path)?;
         let mut current_val = String::new();
         file.read_to_string(&mut current_val)?;
@@ -0,0 +1,180 @@
+//! Unified diffs of hacked manifests
+//!
+//! Manifests are small, a plain longest common subsequence over lines is good enough and
+//! keeps hackerman free of diffing dependencies.
+
+/// Lines of unchanged context around every change
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Keep,
+    Remove,
+    Add,
+}
+
+/// Line level edit script turning `old` into `new`
+fn edits<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Op, &'a str)> {
+    // lcs[i][j] is the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut res = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            res.push((Op::Keep, old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            // removals go first, same as in diffs made by git
+            res.push((Op::Remove, old[i]));
+            i += 1;
+        } else {
+            res.push((Op::Add, new[j]));
+            j += 1;
+        }
+    }
+    res
+}
+
+/// Start of a hunk range as written in the `@@` header, empty ranges point to the line before
+fn range(start: usize, len: usize) -> String {
+    if len == 0 {
+        format!("{start},0")
+    } else {
+        format!("{},{len}", start + 1)
+    }
+}
+
+/// Unified diff between two versions of a file, `path` is relative to where the patch is
+/// applied from. Empty if there are no changes
+///
+/// Output uses git style `a/` and `b/` prefixes and can be applied with `git apply`
+#[must_use]
+pub fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new_lines = new.split_inclusive('\n').collect::<Vec<_>>();
+    let ops = edits(&old_lines, &new_lines);
+    let changes = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, (op, _))| *op != Op::Keep)
+        .map(|(ix, _)| ix)
+        .collect::<Vec<_>>();
+    let Some(&first) = changes.first() else {
+        return String::new();
+    };
+
+    // changes closer than two contexts apart share a hunk
+    let mut hunks = vec![(first, first)];
+    for &ix in &changes[1..] {
+        let last = hunks.last_mut().expect("there is at least one hunk");
+        if ix - last.1 <= 2 * CONTEXT {
+            last.1 = ix;
+        } else {
+            hunks.push((ix, ix));
+        }
+    }
+
+    let mut res = format!("diff --git a/{path} b/{path}\n--- a/{path}\n+++ b/{path}\n");
+    for (first, last) in hunks {
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + CONTEXT + 1).min(ops.len());
+        // line numbers before the hunk
+        let old_start = ops[..start].iter().filter(|(op, _)| *op != Op::Add).count();
+        let new_start = ops[..start]
+            .iter()
+            .filter(|(op, _)| *op != Op::Remove)
+            .count();
+        let old_len = ops[start..end]
+            .iter()
+            .filter(|(op, _)| *op != Op::Add)
+            .count();
+        let new_len = ops[start..end]
+            .iter()
+            .filter(|(op, _)| *op != Op::Remove)
+            .count();
+        res += &format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_len),
+            range(new_start, new_len)
+        );
+        for (op, line) in &ops[start..end] {
+            res.push(match op {
+                Op::Keep => ' ',
+                Op::Remove => '-',
+                Op::Add => '+',
+            });
+            res += line;
+            if !line.ends_with('\n') {
+                res += "\n\\ No newline at end of file\n";
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unified_diff;
+
+    #[test]
+    fn unchanged_file_has_no_diff() {
+        assert_eq!(unified_diff("Cargo.toml", "a\nb\n", "a\nb\n"), "");
+    }
+
+    #[test]
+    fn changes_are_grouped_into_hunks() {
+        let old = (1..=12).map(|i| format!("{i}\n")).collect::<String>();
+        let new = (1..=12)
+            .map(|i| match i {
+                2 => "two\n".to_string(),
+                11 => "11\neleven\n".to_string(),
+                i => format!("{i}\n"),
+            })
+            .collect::<String>();
+        let expected = "\
+diff --git a/alpha/Cargo.toml b/alpha/Cargo.toml
+--- a/alpha/Cargo.toml
++++ b/alpha/Cargo.toml
+@@ -1,5 +1,5 @@
+ 1
+-2
++two
+ 3
+ 4
+ 5
+@@ -9,4 +9,5 @@
+ 9
+ 10
+ 11
++eleven
+ 12
+";
+        assert_eq!(unified_diff("alpha/Cargo.toml", &old, &new), expected);
+    }
+
+    #[test]
+    fn missing_newline_is_marked() {
+        let expected = "\
+diff --git a/Cargo.toml b/Cargo.toml
+--- a/Cargo.toml
++++ b/Cargo.toml
+@@ -1,1 +1,2 @@
+-a
+\\ No newline at end of file
++a
++b
+";
+        assert_eq!(unified_diff("Cargo.toml", "a", "a\nb\n"), expected);
+    }
+}
@@ -2,28 +2,65 @@ use crate::{
     feat_graph::{FeatTarget, Pid},
     hack::{FeatChange, Ty},
 };
-use cargo_metadata::camino::Utf8PathBuf;
+use cargo_metadata::camino::{Utf8Path, Utf8PathBuf};
 use semver::Version;
 use std::collections::{BTreeMap, BTreeSet};
 use tracing::debug;
 
 fn optimize_feats(declared: &BTreeMap<String, Vec<String>>, requested: &mut BTreeSet<String>) {
-    let mut implicit = BTreeSet::new();
-    for req in requested.iter() {
-        for dep in declared.get(req).iter().flat_map(|x| x.iter()) {
-            if let FeatTarget::Named { name } = FeatTarget::from(dep.as_str()) {
-                implicit.insert(name);
+    // all the named features enabled by a feature, following the declarations transitively
+    let closure = |feat: &str| {
+        let mut res = BTreeSet::new();
+        let mut stack = vec![feat];
+        while let Some(cur) = stack.pop() {
+            for dep in declared.get(cur).iter().flat_map(|x| x.iter()) {
+                if let FeatTarget::Named { name } = FeatTarget::from(dep.as_str()) {
+                    if res.insert(name) {
+                        stack.push(name);
+                    }
+                }
             }
         }
+        res
+    };
+    let closures = requested
+        .iter()
+        .map(|req| (req.clone(), closure(req)))
+        .collect::<BTreeMap<_, _>>();
+
+    // a feature is redundant if some other remaining feature enables it, going in reverse
+    // order keeps the first feature of a cycle
+    for req in closures.keys().rev() {
+        if requested
+            .iter()
+            .any(|other| other != req && closures[other].contains(req.as_str()))
+        {
+            requested.remove(req);
+        }
     }
-    for imp in &implicit {
-        requested.remove(*imp);
-    }
+}
+
+/// Check if `requested` features enable everything crate's `default` feature does
+///
+/// Crates without `default` feature never have it enabled, `default` that mentions anything
+/// except named features is only enabled when requested explicitly.
+fn implies_default(declared: &BTreeMap<String, Vec<String>>, requested: &BTreeSet<String>) -> bool {
+    let Some(default) = declared.get("default") else {
+        return false;
+    };
+    requested.contains("default")
+        || default
+            .iter()
+            .all(|dep| match FeatTarget::from(dep.as_str()) {
+                FeatTarget::Named { name } => requested.contains(name),
+                _ => false,
+            })
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{optimize_feats, PackageSource};
+    use super::{forward_slashes, implies_default, optimize_feats, PackageSource, Registries};
+    use semver::Version;
     use std::collections::{BTreeMap, BTreeSet};
 
     fn check(req: &[&str], decl: &[(&str, &[&str])], exp: &[&str]) {
@@ -72,12 +109,122 @@ mod tests {
         );
     }
 
+    #[test]
+    fn optimize_feats_multi_hop() {
+        check(&["a", "b", "c"], &[("a", &["b"]), ("b", &["c"])], &["a"]);
+    }
+
+    #[test]
+    fn optimize_feats_multi_hop_partial() {
+        check(
+            &["a", "c", "d"],
+            &[("a", &["b"]), ("b", &["c"]), ("d", &[])],
+            &["a", "d"],
+        );
+    }
+
+    #[test]
+    fn optimize_feats_cycle() {
+        check(&["a", "b"], &[("a", &["b"]), ("b", &["a"])], &["a"]);
+    }
+
+    fn check_default(req: &[&str], decl: &[(&str, &[&str])]) -> bool {
+        let requested = req.iter().copied().map(String::from).collect();
+        let declared = decl
+            .iter()
+            .map(|(key, vals)| {
+                (
+                    key.to_string(),
+                    vals.iter().copied().map(String::from).collect(),
+                )
+            })
+            .collect();
+        implies_default(&declared, &requested)
+    }
+
+    #[test]
+    fn default_is_implied_by_its_features() {
+        assert!(check_default(&["one"], &[("default", &["one"])]));
+        assert!(check_default(&["default"], &[("default", &["one"])]));
+        assert!(check_default(&[], &[("default", &[])]));
+        assert!(!check_default(&["two"], &[("default", &["one"])]));
+        assert!(!check_default(&["one"], &[("default", &["one", "two"])]));
+        assert!(!check_default(&[], &[("default", &["dep:serde"])]));
+    }
+
+    #[test]
+    fn no_default_feature_is_never_implied() {
+        assert!(!check_default(&["one"], &[("one", &[])]));
+        assert!(!check_default(&["default"], &[]));
+    }
+
     const CRATES_IO: &str = "registry+https://github.com/rust-lang/crates.io-index";
     const GIT_0: &str = "git+https://github.com/rust-lang/cargo.git?branch=main#0227f048";
     const GIT_1: &str = "git+https://github.com/rust-lang/cargo.git?tag=v0.46.0#0227f048";
     const GIT_2: &str = "git+https://github.com/rust-lang/cargo.git?rev=0227f048#0227f048";
     const GIT_3: &str = "git+https://github.com/gyscos/zstd-rs.git#bc874a57";
 
+    #[test]
+    fn registry_names_from_config() -> anyhow::Result<()> {
+        let mut registries = Registries::default();
+        registries.add_config(
+            r#"
+[registries.internal]
+index = "https://git.example.com/index.git"
+[registries]
+sparse = { index = "sparse+https://example.com/index/" }
+"#,
+        );
+        registries.add_config("[registries.internal]\nindex = \"https://other.example.com\"");
+        registries
+            .add_config("[registries.shadowed]\nindex = \"https://git.example.com/index.git\"");
+
+        assert_eq!(
+            registries.name_of("https://git.example.com/index.git"),
+            Some("internal")
+        );
+        assert_eq!(
+            registries.name_of("sparse+https://example.com/index/"),
+            Some("sparse")
+        );
+        assert_eq!(registries.name_of(PackageSource::CRATES_IO_URL), None);
+
+        let sparse = PackageSource::try_from("sparse+https://example.com/index/")?;
+        let ver = Version::new(1, 2, 3);
+        let mut table = toml_edit::InlineTable::new();
+        sparse.insert_into(&ver, Some("sparse"), &mut table);
+        assert_eq!(
+            table.to_string(),
+            r#"{ version = "1.2.3", registry = "sparse" }"#
+        );
+
+        let mut table = toml_edit::InlineTable::new();
+        sparse.insert_into(&ver, None, &mut table);
+        assert_eq!(
+            table.to_string(),
+            r#"{ version = "1.2.3", registry-index = "sparse+https://example.com/index/" }"#
+        );
+
+        let mut table = toml_edit::InlineTable::new();
+        PackageSource::CRATES_IO.insert_into(&ver, None, &mut table);
+        assert_eq!(table.to_string(), r#"{ version = "1.2.3" }"#);
+        Ok(())
+    }
+
+    #[test]
+    fn paths_use_forward_slashes() {
+        assert_eq!(forward_slashes(r"..\..\8a\delta", '\\'), "../../8a/delta");
+        assert_eq!(forward_slashes("../odd\\name", '/'), "../odd\\name");
+
+        let ver = Version::new(1, 2, 3);
+        let mut table = toml_edit::InlineTable::new();
+        let source = PackageSource::File {
+            path: "../beta".into(),
+        };
+        source.insert_into(&ver, None, &mut table);
+        assert_eq!(table.to_string(), r#"{ path = "../beta" }"#);
+    }
+
     #[test]
     fn parse_sources() -> anyhow::Result<()> {
         PackageSource::try_from(CRATES_IO)?;
@@ -94,6 +241,9 @@ impl<'a> TryFrom<&'a str> for PackageSource<'a> {
     fn try_from(value: &'a str) -> Result<Self, Self::Error> {
         if let Some(registry) = value.strip_prefix("registry+") {
             Ok(PackageSource::Registry(registry))
+        } else if value.starts_with("sparse+") {
+            // sparse registries are configured with the prefix included
+            Ok(PackageSource::Registry(value))
         } else if let Some(repo) = value.strip_prefix("git+") {
             if let Some((url, _)) = repo.split_once('#') {
                 Ok(PackageSource::Git(url))
@@ -108,29 +258,55 @@ impl<'a> TryFrom<&'a str> for PackageSource<'a> {
 
 impl<'a> ChangePackage<'a> {
     #[allow(clippy::similar_names)]
-    pub fn make(importer: Pid<'a>, importee: FeatChange<'a>) -> anyhow::Result<Self> {
+    pub fn make(
+        importer: Pid<'a>,
+        importee: FeatChange<'a>,
+        registries: &Registries,
+    ) -> anyhow::Result<Self> {
         let FeatChange {
             pid: importee,
             ty,
             rename,
             features: mut feats,
+            missing,
+            declared,
         } = importee;
         let package = importee.package();
+        // unified set can enable everything default does without naming it, in which case
+        // keeping default features on is the same thing and gives a shorter manifest
+        if implies_default(&package.features, &feats) {
+            feats.insert("default".to_string());
+        }
         optimize_feats(&package.features, &mut feats);
         // we care if package we are importing comes with the default key, not
         // the package that imports
         let has_default = importee.package().features.contains_key("default");
 
-        if let Some(src) = &package.source {
-            let source = PackageSource::try_from(src.repr.as_str())?;
+        // patched dependencies keep the declared source so `[patch]` still applies to them
+        let source = declared.or_else(|| package.source.as_ref().map(|src| src.repr.as_str()));
+        if let Some(src) = source {
+            let source = PackageSource::try_from(src)?;
+            let registry = match source {
+                PackageSource::Registry(index) if index != PackageSource::CRATES_IO_URL => {
+                    let name = registries.name_of(index);
+                    if name.is_none() {
+                        debug!("No configured name for registry {index}, using the index url");
+                    }
+                    name.map(str::to_string)
+                }
+                _ => None,
+            };
             Ok(ChangePackage {
                 name: package.name.clone(),
                 ty,
                 version: package.version.clone(),
                 source,
+                registry,
                 feats,
+                missing,
                 rename,
                 has_default,
+                workspace: false,
             })
         } else {
             let source = match relative_import_dir(importer, importee) {
@@ -154,14 +330,43 @@ impl<'a> ChangePackage<'a> {
                 ty,
                 version: package.version.clone(),
                 source,
+                registry: None,
                 feats,
+                missing,
                 rename,
                 has_default,
+                workspace: false,
             })
         }
     }
 }
 
+impl ChangePackage<'_> {
+    /// Description of the change for `hack --report`
+    #[must_use]
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "version": self.version.to_string(),
+            "source": self.source.to_string(),
+            "registry": self.registry,
+            "ty": self.ty.to_string(),
+            "features": self.feats,
+            "rename": self.rename,
+        })
+    }
+}
+
+/// Manifests hacked on Windows should be the same as ones hacked elsewhere, cargo accepts `/`
+/// on all the platforms
+fn forward_slashes(path: &str, separator: char) -> String {
+    if separator == '/' {
+        path.to_string()
+    } else {
+        path.replace(separator, "/")
+    }
+}
+
 #[allow(clippy::similar_names)]
 fn relative_import_dir(importer: Pid, importee: Pid) -> Option<Utf8PathBuf> {
     let importer_dir = &importer.package().manifest_path.parent()?;
@@ -175,28 +380,47 @@ pub struct ChangePackage<'a> {
     pub ty: Ty,
     pub version: Version,
     pub source: PackageSource<'a>,
+    /// Name of an alternative registry the package comes from, as configured by cargo
+    pub registry: Option<String>,
     pub feats: BTreeSet<String>,
+    /// Features the member doesn't enable on its own, as opposed to `feats` they are not
+    /// shortened to what implies them
+    pub missing: BTreeSet<String>,
     pub rename: bool,
+    /// Package declares a `default` feature, `default-features = false` is meaningless otherwise
+    /// and is never written
     pub has_default: bool,
+    /// Dependency is inherited from `[workspace.dependencies]`, `feats` are the extra ones
+    pub workspace: bool,
 }
 
 impl PackageSource<'_> {
-    pub fn insert_into(&self, ver: &Version, table: &mut toml_edit::InlineTable) {
+    pub fn insert_into(
+        &self,
+        ver: &Version,
+        registry: Option<&str>,
+        table: &mut toml_edit::InlineTable,
+    ) {
         match self {
-            PackageSource::Registry(_) => {
+            PackageSource::Registry(index) => {
                 table.insert("version", toml_edit::Value::from(ver.to_string()));
+                if let Some(name) = registry {
+                    table.insert("registry", toml_edit::Value::from(name));
+                } else if *index != Self::CRATES_IO_URL {
+                    table.insert("registry-index", toml_edit::Value::from(*index));
+                }
             }
             PackageSource::Git(url) => {
                 table.insert("git", toml_edit::Value::from(*url));
             }
-            PackageSource::File { path } => {
-                table.insert("path", toml_edit::Value::from(path.to_string()));
+            PackageSource::File { .. } => {
+                table.insert("path", toml_edit::Value::from(self.to_string()));
             }
         }
     }
 }
 
-#[derive(Debug, Hash)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 #[allow(clippy::module_name_repetitions)]
 pub enum PackageSource<'a> {
     Registry(&'a str),
@@ -205,8 +429,64 @@ pub enum PackageSource<'a> {
 }
 
 impl PackageSource<'_> {
-    pub const CRATES_IO: Self =
-        PackageSource::Registry("https://github.com/rust-lang/crates.io-index");
+    pub const CRATES_IO_URL: &'static str = "https://github.com/rust-lang/crates.io-index";
+    pub const CRATES_IO: Self = PackageSource::Registry(Self::CRATES_IO_URL);
+}
+
+/// Alternative registries configured for cargo, used to refer to registries by name
+#[derive(Debug, Default)]
+pub struct Registries {
+    /// index url to registry name
+    names: BTreeMap<String, String>,
+}
+
+impl Registries {
+    /// Collect registries from `CARGO_REGISTRIES_<NAME>_INDEX` variables, cargo configs
+    /// starting from `root` and up, and then from `CARGO_HOME`
+    ///
+    /// The closest definition wins, same as in cargo itself
+    #[must_use]
+    pub fn load(root: &Utf8Path) -> Self {
+        let mut res = Self::default();
+        for (var, index) in std::env::vars() {
+            if let Some(name) = var
+                .strip_prefix("CARGO_REGISTRIES_")
+                .and_then(|v| v.strip_suffix("_INDEX"))
+            {
+                let name = name.to_lowercase().replace('_', "-");
+                res.names.entry(index).or_insert(name);
+            }
+        }
+
+        for path in crate::config::config_files(root.as_std_path()) {
+            if let Ok(config) = std::fs::read_to_string(path) {
+                res.add_config(&config);
+            }
+        }
+        res
+    }
+
+    fn add_config(&mut self, config: &str) {
+        let Ok(config) = config.parse::<toml_edit::Document>() else {
+            return;
+        };
+        let Some(registries) = config.get("registries").and_then(|r| r.as_table_like()) else {
+            return;
+        };
+        for (name, registry) in registries.iter() {
+            if let Some(index) = registry.get("index").and_then(|i| i.as_str()) {
+                self.names
+                    .entry(index.to_string())
+                    .or_insert_with(|| name.to_string());
+            }
+        }
+    }
+
+    /// Name of the registry with this index url
+    #[must_use]
+    pub fn name_of(&self, index: &str) -> Option<&str> {
+        self.names.get(index).map(String::as_str)
+    }
 }
 
 impl std::fmt::Display for PackageSource<'_> {
@@ -214,7 +494,9 @@ impl std::fmt::Display for PackageSource<'_> {
         match self {
             PackageSource::Registry(_reg) => f.write_str("registry"),
             PackageSource::Git(url) => write!(f, "{url}"),
-            PackageSource::File { path } => path.fmt(f),
+            PackageSource::File { path } => {
+                f.write_str(&forward_slashes(path.as_str(), std::path::MAIN_SEPARATOR))
+            }
         }
     }
 }
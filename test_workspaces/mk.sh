@@ -1,9 +1,29 @@
 #!/bin/sh -ue
 
 
+# paths are replaced with a placeholder so fixtures don't depend on the checkout location,
+# tests must not read anything from the workspace root of fixture metadata
+meta() {
+    cargo metadata --manifest-path "$1/Cargo.toml" --format-version 1 --offline \
+        | sed "s|$PWD|/home/pacak/ej/cargo-hackerman/demo|g" > "$1/metadata.json"
+}
+
 #cargo metadata --manifest-path 1/Cargo.toml --format-version 1 > 1/metadata.json
 #cargo metadata --manifest-path 2/alpha/Cargo.toml --format-version 1 > 2/metadata.json
 #cargo metadata --manifest-path 3/alpha/Cargo.toml --format-version 1 > 3/metadata.json
 #cargo metadata --manifest-path 4/alpha/Cargo.toml --format-version 1 > 4/metadata.json
-cargo metadata --manifest-path 5/Cargo.toml --format-version 1 > 5/metadata.json
+meta 5
 
+meta 11
+meta 12
+meta 13
+meta 14
+meta 15
+meta 16
+meta 17
+meta 18
+meta 19
+meta 20
+meta 21
+meta 22
+meta 24